@@ -2,37 +2,383 @@ use anyhow::{Result, anyhow};
 use reqwest::Client;
 use serde_json::json;
 
-use crate::types::{ChatMessage, PageState, Step};
+use crate::types::{ChatMessage, Decision, PageState, Redactor, Step, parse_decision, parse_step};
 
 const MODEL: &str = "gpt-5.2"; // Change to "gpt-5.2" or your preferred model
 
-const SYSTEM_PROMPT: &str = r#"You are a browser automation agent. You control a real Chrome browser by issuing ONE step at a time as JSON.
+/// With SUMMARIZE_HISTORY=1, history longer than this gets folded into a summary.
+const HISTORY_SUMMARY_THRESHOLD: usize = 20;
+/// Most recent messages left verbatim when summarizing.
+const HISTORY_KEEP_RECENT: usize = 8;
+
+/// Retries for a rate-limited request, waiting 2s, 4s, 8s...
+const RATE_LIMIT_RETRIES: u32 = 3;
+
+/// What went wrong on the API side, so callers can react to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiErrorKind {
+    /// The conversation no longer fits in the model's context window.
+    ContextLength,
+    /// Bad or missing credentials; retrying won't help.
+    Auth,
+    /// Too many requests; worth retrying after a pause.
+    RateLimit,
+    Other,
+}
+
+/// A non-success reply from the chat-completions API.
+#[derive(Debug)]
+pub struct ApiError {
+    pub kind: ApiErrorKind,
+    pub status: u16,
+    pub message: String,
+}
+
+impl ApiError {
+    /// Classify an error reply from its HTTP status and `error` object.
+    fn from_response(status: u16, body: &serde_json::Value) -> Self {
+        let error = &body["error"];
+        let message = error["message"]
+            .as_str()
+            .unwrap_or("Unknown API error")
+            .to_string();
+        let code = error["code"].as_str().unwrap_or_default();
+        let error_type = error["type"].as_str().unwrap_or_default();
+
+        let kind = if code == "context_length_exceeded" || message.contains("context length") {
+            ApiErrorKind::ContextLength
+        } else if status == 401
+            || status == 403
+            || code == "invalid_api_key"
+            || error_type == "authentication_error"
+        {
+            ApiErrorKind::Auth
+        } else if status == 429 && code != "insufficient_quota" {
+            ApiErrorKind::RateLimit
+        } else {
+            ApiErrorKind::Other
+        };
+        Self {
+            kind,
+            status,
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OpenAI API error ({}): {}", self.status, self.message)?;
+        if self.kind == ApiErrorKind::Auth {
+            write!(f, ". Check OPENAI_API_KEY")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+const SUMMARY_PROMPT: &str = "Summarize this browser automation transcript for the agent that will continue it. \
+Keep the user's task(s), which pages were visited, what worked, what failed, and any extracted facts. \
+Be compact: plain sentences, no JSON, under 200 words.";
+
+const SYSTEM_PROMPT: &str = r##"You are a browser automation agent. You control a real Chrome browser by issuing ONE step at a time as JSON.
 
 Available actions:
 - {"action":"Navigate","url":"https://..."}
+- {"action":"FollowLink","href_contains":"/pricing"}  opens the first visible link whose URL contains the text, when the URL is predictable but the link text is not
 - {"action":"WaitFor","selector":"[data-eid=\"[e0]\"]","timeout_ms":5000}
-- {"action":"TypeInto","selector":"[data-eid=\"[e0]\"]","text":"search query"}
+- {"action":"WaitForGone","selector":".spinner","timeout_ms":10000}  waits until a loader/overlay is removed or hidden
+- {"action":"WaitForUrl","pattern":"/dashboard","timeout_ms":10000}  waits until the URL contains pattern (use * as a wildcard to match the whole URL)
+- {"action":"WaitForStable","selector":"[data-eid=\"[e0]\"]","timeout_ms":3000}  waits until an animated element (drawer, menu, modal) stops moving, before clicking inside it
+- {"action":"TypeInto","selector":"[data-eid=\"[e0]\"]","text":"search query"}  also works on "editable" rich-text areas (e.g. email compose)
+- {"action":"TypeByLabel","label":"Email","text":"me@example.com"}  types into the form field with that label, placeholder or aria-label
+- {"action":"ClearInput","selector":"[data-eid=\"[e0]\"]"}  empties a field without typing (e.g. to reset a filter)
 - {"action":"Click","selector":"[data-eid=\"[e0]\"]"}
+- {"action":"ClickNth","selector":"#search a h3","index":2}  clicks the 3rd match (index is 0-based)
+- {"action":"RightClick","selector":"[data-eid=\"[e0]\"]"}  opens an element's context menu; the menu appears in the next DOM
 - {"action":"PressKey","key":"Enter"}
+- {"action":"SetChecked","selector":"[data-eid=\"[e0]\"]","checked":true}  checks or unchecks a checkbox/radio only if needed
+- {"action":"SubmitForm","selector":"[data-eid=\"[e0]\"]"}  submits the form containing that element (or a form's name)
 - {"action":"Extract","selector":"body","label":"main_content"}
+- {"action":"ExtractLinks","label":"links"}  lists up to 100 links on the page as JSON [{text, href}]
+- {"action":"ExtractTable","selector":"table","label":"prices"}  reads a table (or the first table inside selector) as JSON rows of cell text
+- {"action":"CountElements","selector":".result","label":"result_count"}  reports how many elements match, e.g. before paging or "load more"
+- {"action":"GetAttribute","selector":"[data-eid=\"[e0]\"]","attribute":"href","label":"link"}  reads one attribute (href, data-id, ...) or an input's current value/checked state
 - {"action":"Screenshot"}
+- {"action":"FullPageScreenshot"}  saves a PNG of the whole scrolling page; reports the file path
+- {"action":"ScreenshotElement","selector":"[data-eid=\"[e0]\"]"}  saves a PNG of just one element; reports the file path
+- {"action":"PageInfo","label":"scroll"}  reports scrollY, innerHeight, scrollHeight and moreBelow
+- {"action":"WaitForDownload","timeout_ms":15000}
+- {"action":"ReadDownload","timeout_ms":15000,"label":"export"}  waits for a download and extracts its text (small CSV/JSON/text files only)
+- {"action":"ScrollIntoView","selector":"[data-eid=\"[e0]\"]"}  centers an element on screen, including inside scrollable panels (Click and TypeInto already do this)
+- {"action":"DismissModal"}  closes a dialog/popup covering the page (cookie banners, newsletter prompts) instead of hunting for its close button
 - {"action":"NewTab"}
+- {"action":"SwitchToOldestTab"}  returns to the tab the task started in (e.g. after a popup)
+- {"action":"Activate"}  brings the current tab to the front, for widgets that only work in a focused tab
+- {"action":"GoToHistory","title_contains":"Search results"}  reopens the latest page from "Recent pages" whose title contains the text
+- {"action":"Assert","selector":".cart-item","text_contains":"Blue mug"}  verifies the page; if the element is missing or lacks the text, the whole task fails (either field may be null)
 - {"action":"Done","summary":"Completed: found the answer is 42"}
 
+Advanced actions (only when the task needs them):
+- {"action":"SetWindowBounds","width":1280,"height":800,"maximize":false}  optional setup: resizes the browser window (with maximize:true, width/height may be null)
+- {"action":"GetStorage","key":"auth_token","label":"token"}  reads localStorage[key]
+- {"action":"SetStorage","key":"consent","value":"accepted"}  writes localStorage[key]
+- {"action":"ClearCookies","current_origin":false}  logs out by clearing cookies; true limits it to the current site
+- {"action":"ClearExtractions"}  drops the content of earlier "Extracted [...]" results from the conversation once you've used them (saved results are kept)
+- {"action":"PerfMetrics","label":"timing"}  reports the current page's load timings in ms (ttfb, dom_content_loaded, load) as JSON
+- {"action":"GrantPermission","name":"geolocation"}  allows a permission prompt (geolocation, notifications, audioCapture, videoCapture, clipboardReadWrite, clipboardSanitizedWrite, midi) for the current site
+
 Rules:
 1. Return ONLY a single JSON object per response. No markdown, no explanation.
 2. Use the [eN] element IDs from the DOM snapshot to target elements. Use selector format: [data-eid="[eN]"]
 3. After Navigate, the system will show you the new page DOM. Decide your next step based on what you see.
-4. Use TypeInto to fill inputs, then PressKey with "Enter" to submit. Or Click the submit button.
+4. Use TypeInto to fill inputs, then PressKey with "Enter" or SubmitForm to submit. Or Click the submit button.
 5. When the user's task is accomplished, use Done with a summary of what was achieved.
 6. If you encounter an error, try an alternative approach. If stuck after 3 attempts, use Done to explain.
-7. Keep steps minimal. Do not over-navigate."#;
+7. Keep steps minimal. Do not over-navigate.
+8. After clicking something that downloads a file, use WaitForDownload to get the saved file path.
+9. Before declaring Done on a long page, use PageInfo to check whether more content is below.
+10. Click and TypeInto accept an optional "fallbacks" list of alternative selectors, tried in order if the first matches nothing, e.g. {"action":"Click","selector":"[data-eid=\"[e4]\"]","fallbacks":["button[type=submit]"]}. Use it when unsure the element is still there.
+11. When the task asks you to verify or check something, use Assert for the check rather than judging it yourself. A failed Assert ends the task as failed.
+12. You may add an optional "reason" field with a one-line rationale, e.g. {"action":"Click","selector":"[data-eid=\"[e4]\"]","reason":"Opens the pricing page"}."##;
+
+/// Fields of each Step variant, used to build the STRUCTURED_OUTPUT schema.
+/// A `?` suffix marks a nullable field and `string[]` a list of strings.
+/// Keep in sync with `types::Step`.
+const STEP_FIELDS: &[(&str, &[(&str, &str)])] = &[
+    ("Navigate", &[("url", "string")]),
+    ("FollowLink", &[("href_contains", "string")]),
+    (
+        "WaitFor",
+        &[("selector", "string"), ("timeout_ms", "integer")],
+    ),
+    (
+        "WaitForGone",
+        &[("selector", "string"), ("timeout_ms", "integer")],
+    ),
+    (
+        "WaitForUrl",
+        &[("pattern", "string"), ("timeout_ms", "integer")],
+    ),
+    (
+        "WaitForStable",
+        &[("selector", "string"), ("timeout_ms", "integer")],
+    ),
+    (
+        "TypeInto",
+        &[
+            ("selector", "string"),
+            ("text", "string"),
+            ("fallbacks", "string[]"),
+        ],
+    ),
+    ("TypeByLabel", &[("label", "string"), ("text", "string")]),
+    ("ClearInput", &[("selector", "string")]),
+    (
+        "Click",
+        &[("selector", "string"), ("fallbacks", "string[]")],
+    ),
+    ("ClickNth", &[("selector", "string"), ("index", "integer")]),
+    ("RightClick", &[("selector", "string")]),
+    ("PressKey", &[("key", "string")]),
+    ("SubmitForm", &[("selector", "string")]),
+    (
+        "SetChecked",
+        &[("selector", "string"), ("checked", "boolean")],
+    ),
+    ("Extract", &[("selector", "string"), ("label", "string")]),
+    ("ExtractLinks", &[("label", "string")]),
+    (
+        "ExtractTable",
+        &[("selector", "string"), ("label", "string")],
+    ),
+    (
+        "CountElements",
+        &[("selector", "string"), ("label", "string")],
+    ),
+    (
+        "GetAttribute",
+        &[
+            ("selector", "string"),
+            ("attribute", "string"),
+            ("label", "string"),
+        ],
+    ),
+    ("Screenshot", &[]),
+    ("FullPageScreenshot", &[]),
+    ("ScreenshotElement", &[("selector", "string")]),
+    ("PageInfo", &[("label", "string")]),
+    ("WaitForDownload", &[("timeout_ms", "integer")]),
+    (
+        "ReadDownload",
+        &[("timeout_ms", "integer"), ("label", "string")],
+    ),
+    ("GetStorage", &[("key", "string"), ("label", "string")]),
+    ("SetStorage", &[("key", "string"), ("value", "string")]),
+    ("ClearCookies", &[("current_origin", "boolean")]),
+    ("PerfMetrics", &[("label", "string")]),
+    ("ClearExtractions", &[]),
+    ("GrantPermission", &[("name", "string")]),
+    (
+        "SetWindowBounds",
+        &[
+            ("width", "integer?"),
+            ("height", "integer?"),
+            ("maximize", "boolean"),
+        ],
+    ),
+    (
+        "Assert",
+        &[("selector", "string?"), ("text_contains", "string?")],
+    ),
+    ("Done", &[("summary", "string")]),
+    ("ScrollIntoView", &[("selector", "string")]),
+    ("DismissModal", &[]),
+    ("NewTab", &[]),
+    ("SwitchToOldestTab", &[]),
+    ("Activate", &[]),
+    ("GoToHistory", &[("title_contains", "string")]),
+];
+
+/// JSON schema for `response_format`. Strict mode needs an object at the
+/// root, so the step itself sits under a `step` key.
+fn step_response_format() -> serde_json::Value {
+    let variants: Vec<serde_json::Value> = STEP_FIELDS
+        .iter()
+        .map(|(action, fields)| {
+            let mut properties = serde_json::Map::new();
+            properties.insert("action".into(), json!({"type": "string", "enum": [action]}));
+            let mut required = vec![json!("action")];
+            for (name, ty) in fields.iter() {
+                let schema = match (*ty, ty.strip_suffix('?')) {
+                    ("string[]", _) => json!({"type": "array", "items": {"type": "string"}}),
+                    (_, Some(inner)) => json!({"type": [inner, "null"]}),
+                    (ty, None) => json!({"type": ty}),
+                };
+                properties.insert(name.to_string(), schema);
+                required.push(json!(name));
+            }
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+                "additionalProperties": false,
+            })
+        })
+        .collect();
+
+    json!({
+        "type": "json_schema",
+        "json_schema": {
+            "name": "next_step",
+            "strict": true,
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "step": {"anyOf": variants},
+                    "reason": {"type": ["string", "null"]},
+                },
+                "required": ["step", "reason"],
+                "additionalProperties": false,
+            },
+        },
+    })
+}
+
+/// Index of the message that started the current task.
+fn latest_task(conversation: &[ChatMessage]) -> Option<usize> {
+    conversation
+        .iter()
+        .rposition(|m| m.role == "user" && m.content.starts_with("Task: "))
+}
+
+/// The messages before `cutoff` that `summarize_history` folds: all but the
+/// system prompt and the current task, which /resume and `trim_history`
+/// need to find verbatim.
+fn messages_to_summarize(conversation: &[ChatMessage], cutoff: usize) -> Vec<&ChatMessage> {
+    let task = latest_task(conversation);
+    (1..cutoff)
+        .filter(|&i| Some(i) != task)
+        .map(|i| &conversation[i])
+        .collect()
+}
+
+/// Swap the messages `messages_to_summarize` picked for a single summary,
+/// placed after the current task if that was among them.
+fn replace_with_summary(conversation: &mut Vec<ChatMessage>, cutoff: usize, summary: &str) {
+    let summary = ChatMessage {
+        role: "system".to_string(),
+        content: format!("Context so far: {}", summary),
+    };
+    let kept = match latest_task(conversation).filter(|&i| i < cutoff) {
+        Some(task) => vec![conversation[task].clone(), summary],
+        None => vec![summary],
+    };
+    conversation.splice(1..cutoff, kept);
+}
+
+/// Replace the content of each `Extracted [label]: ...` block `observe`
+/// appended to `observation`, keeping the labels. None if there were none.
+///
+/// A block runs until the next section `observe` or `attach_screenshot`
+/// writes, so notes added after the extractions survive.
+fn clear_extracted_text(observation: &str) -> Option<String> {
+    const MARKER: &str = "\n\nExtracted [";
+    const SECTIONS: &[&str] = &[
+        MARKER,
+        "\n\nNOTE: ",
+        "\n\nERROR from last step: ",
+        "\n\nConsole errors:",
+        "\n\nRecent pages:",
+    ];
+    let (head, mut rest) = observation.split_once(MARKER)?;
+    let mut cleared = head.to_string();
+    loop {
+        let end = SECTIONS
+            .iter()
+            .filter_map(|section| rest.find(section))
+            .min()
+            .unwrap_or(rest.len());
+        let block = &rest[..end];
+        let label = block.split_once("]: ").map_or(block, |(label, _)| label);
+        cleared.push_str(&format!("{}{}]: (cleared)", MARKER, label));
+        rest = &rest[end..];
+        match rest.strip_prefix(MARKER) {
+            Some(next) => rest = next,
+            None => break,
+        }
+    }
+    cleared.push_str(rest);
+    Some(cleared)
+}
+
+/// Unwrap a reply produced under `step_response_format`.
+fn parse_structured_step(content: &str) -> Result<Decision> {
+    let mut reply: serde_json::Value = serde_json::from_str(content)?;
+    Ok(Decision {
+        step: serde_json::from_value(reply["step"].take())?,
+        reason: reply["reason"].as_str().map(String::from),
+    })
+}
 
 pub struct Brain {
     client: Client,
     api_key: String,
+    completions_url: String,
+    /// Azure expects an `api-key` header instead of a bearer token.
+    azure_auth: bool,
     conversation: Vec<ChatMessage>,
     memory_path: std::path::PathBuf,
+    summarize_history: bool,
+    /// Ask the API to enforce the Step schema instead of trusting free text.
+    structured_output: bool,
+    /// Running total of `usage.total_tokens` across every API call.
+    tokens_used: u64,
+    /// Applied to everything written to memory.json and to logged replies.
+    redactor: Redactor,
+    /// Screenshot to attach to the next request only (VISION_FALLBACK=1).
+    pending_screenshot: Option<String>,
 }
 
 impl Brain {
@@ -40,6 +386,16 @@ impl Brain {
         let api_key = std::env::var("OPENAI_API_KEY")
             .map_err(|_| anyhow!("OPENAI_API_KEY not set in environment"))?;
 
+        // Azure and corporate proxies serve the same API from a different base URL
+        let base_url = std::env::var("OPENAI_BASE_URL")
+            .unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+        let mut completions_url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+        if let Ok(version) = std::env::var("OPENAI_API_VERSION") {
+            completions_url.push_str(&format!("?api-version={}", version));
+        }
+        let azure_auth = std::env::var("OPENAI_API_TYPE").is_ok_and(|v| v == "azure");
+        eprintln!("[Brain] Using endpoint: {}", completions_url);
+
         let conversation = vec![ChatMessage {
             role: "system".to_string(),
             content: SYSTEM_PROMPT.to_string(),
@@ -48,8 +404,15 @@ impl Brain {
         let mut brain = Self {
             client: Client::new(),
             api_key,
+            completions_url,
+            azure_auth,
             conversation,
             memory_path: std::path::PathBuf::from("memory.json"),
+            summarize_history: std::env::var("SUMMARIZE_HISTORY").is_ok_and(|v| v == "1"),
+            structured_output: std::env::var("STRUCTURED_OUTPUT").is_ok_and(|v| v == "1"),
+            tokens_used: 0,
+            redactor: Redactor::from_env(),
+            pending_screenshot: None,
         };
 
         // Try to load existing memory
@@ -72,12 +435,32 @@ impl Brain {
     }
 
     fn save_memory(&self) {
+        // The LLM still sees real values; only the file on disk is masked
+        let masked: Vec<ChatMessage> = self
+            .conversation
+            .iter()
+            .map(|m| ChatMessage {
+                role: m.role.clone(),
+                content: self.redactor.mask(&m.content),
+            })
+            .collect();
         if let Ok(file) = std::fs::File::create(&self.memory_path) {
             let writer = std::io::BufWriter::new(file);
-            let _ = serde_json::to_writer_pretty(writer, &self.conversation);
+            let _ = serde_json::to_writer_pretty(writer, &masked);
         }
     }
 
+    /// Whether `step` types a value the redactor already masks.
+    pub fn is_secret(&self, step: &Step) -> bool {
+        self.redactor.matches(step)
+    }
+
+    /// Mask `value` in memory.json from now on (e.g. a typed password).
+    pub fn add_secret(&mut self, value: &str) {
+        self.redactor.add(value);
+        self.save_memory();
+    }
+
     /// Start a new task. Preserves history/context.
     pub fn start_task(&mut self, user_prompt: &str) {
         // self.conversation.truncate(1); // OLD: Wiped history
@@ -93,17 +476,58 @@ impl Brain {
         self.save_memory();
     }
 
+    /// True if memory holds a task whose last assistant reply isn't a `Done` step.
+    pub fn has_unfinished_task(&self) -> bool {
+        let Some(task_start) = latest_task(&self.conversation) else {
+            return false;
+        };
+
+        let last_reply = self.conversation[task_start..]
+            .iter()
+            .rev()
+            .find(|m| m.role == "assistant");
+
+        match last_reply {
+            Some(m) => !matches!(parse_step(&m.content), Ok(Step::Done { .. })),
+            None => true,
+        }
+    }
+
     /// Feed observation back to the LLM.
     pub fn observe(&mut self, page_state: &PageState) {
+        let status = match page_state.http_status {
+            Some(code) if code >= 400 => format!("\nHTTP status: {} (error page)", code),
+            Some(code) => format!("\nHTTP status: {}", code),
+            None => String::new(),
+        };
         let mut observation = format!(
-            "Page URL: {}\nTitle: {}\n\nDOM:\n{}",
-            page_state.url, page_state.title, page_state.dom_snapshot
+            "Page URL: {}{}\nTitle: {}\n\nDOM:\n{}",
+            page_state.url, status, page_state.title, page_state.dom_snapshot
         );
 
         if let Some(ref err) = page_state.error {
             observation.push_str(&format!("\n\nERROR from last step: {}", err));
         }
 
+        for note in &page_state.notes {
+            observation.push_str(&format!("\n\nNOTE: {}", note));
+        }
+
+        if !page_state.console_errors.is_empty() {
+            observation.push_str("\n\nConsole errors:");
+            for entry in &page_state.console_errors {
+                observation.push_str(&format!("\n- {}", entry));
+            }
+        }
+
+        // The current page is always last, so a single entry adds nothing
+        if page_state.recent_pages.len() > 1 {
+            observation.push_str("\n\nRecent pages:");
+            for (title, url) in &page_state.recent_pages {
+                observation.push_str(&format!("\n- {} ({})", title, url));
+            }
+        }
+
         for ext in &page_state.extracted {
             observation.push_str(&format!("\n\nExtracted [{}]: {}", ext.label, ext.content));
         }
@@ -115,45 +539,249 @@ impl Brain {
         self.save_memory();
     }
 
+    /// Fold the oldest messages into a single "context so far" note.
+    ///
+    /// Goes straight to `chat_completion`, so summarizing never triggers
+    /// another summary. The system prompt and the current task are always kept.
+    async fn summarize_history(&mut self) -> Result<()> {
+        let cutoff = self.conversation.len() - HISTORY_KEEP_RECENT;
+        let folded = messages_to_summarize(&self.conversation, cutoff);
+        if folded.is_empty() {
+            return Ok(());
+        }
+        let count = folded.len();
+        let transcript = folded
+            .iter()
+            .map(|m| format!("{}: {}", m.role, m.content))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let summary = self
+            .chat_completion(
+                vec![
+                    json!({"role": "system", "content": SUMMARY_PROMPT}),
+                    json!({"role": "user", "content": transcript}),
+                ],
+                None,
+            )
+            .await?;
+
+        eprintln!("[Brain] Summarized {} old messages.", count);
+        replace_with_summary(&mut self.conversation, cutoff, summary.trim());
+        self.save_memory();
+        Ok(())
+    }
+
+    /// Blank out earlier extractions in the history to save tokens.
+    pub fn clear_extractions(&mut self) {
+        let mut count = 0;
+        for message in self.conversation.iter_mut().filter(|m| m.role == "user") {
+            if let Some(cleared) = clear_extracted_text(&message.content) {
+                message.content = cleared;
+                count += 1;
+            }
+        }
+        eprintln!("[Brain] Cleared extractions from {} observations.", count);
+        self.save_memory();
+    }
+
+    /// Tell the LLM the user vetoed its last proposed step.
+    pub fn step_rejected(&mut self) {
+        self.conversation.push(ChatMessage {
+            role: "user".to_string(),
+            content: "The user rejected that step. Do not repeat it; propose a different step."
+                .to_string(),
+        });
+        self.save_memory();
+    }
+
     /// Ask the LLM for the next step.
-    pub async fn decide_next_step(&mut self) -> Result<Step> {
-        let messages: Vec<serde_json::Value> = self
+    pub async fn decide_next_step(&mut self) -> Result<Decision> {
+        if self.summarize_history
+            && self.conversation.len() > HISTORY_SUMMARY_THRESHOLD
+            && let Err(e) = self.summarize_history().await
+        {
+            eprintln!("[Brain] Warning: History summary failed: {:#}", e);
+        }
+
+        // Check token limit helper (naive)
+        if self.conversation.len() > 20 {
+            eprintln!(
+                "[Brain] Warning: Conversation history is long ({})",
+                self.conversation.len()
+            );
+        }
+
+        let content = self.complete_with_recovery().await;
+        // Screenshots are sent once, never kept in history or memory
+        self.pending_screenshot = None;
+        let content = content?;
+
+        let decision = if self.structured_output {
+            parse_structured_step(&content).or_else(|_| parse_decision(&content))
+        } else {
+            parse_decision(&content)
+        };
+
+        // Typed text may be a password the Agent hasn't flagged yet, so
+        // don't log it verbatim
+        match &decision {
+            Ok(d) if d.step.secret().is_some() => {
+                eprintln!(
+                    "[Brain] LLM says: {}",
+                    serde_json::to_string(&d.step.redacted())?
+                )
+            }
+            _ => eprintln!("[Brain] LLM says: {}", self.redactor.mask(&content)),
+        }
+
+        // Record assistant response in conversation history. Structured
+        // replies are stored as plain step JSON so history reads the same.
+        let recorded = match (&decision, self.structured_output) {
+            (Ok(decision), true) => serde_json::to_string(decision)?,
+            _ => content.clone(),
+        };
+        self.conversation.push(ChatMessage {
+            role: "assistant".to_string(),
+            content: recorded,
+        });
+        // Steps that type text are saved by the next `observe`, once the
+        // Agent has had a chance to flag the text as a secret
+        if !decision.as_ref().is_ok_and(|d| d.step.secret().is_some()) {
+            self.save_memory(); // Save after assistant reply
+        }
+
+        decision.inspect_err(|e| {
+            eprintln!(
+                "[Brain] JSON Parse Error: {}. Content: {}",
+                e,
+                self.redactor.mask(&content)
+            );
+        })
+    }
+
+    /// Request the next reply, trimming history when it no longer fits and
+    /// backing off when rate limited. Other API errors are returned as is.
+    async fn complete_with_recovery(&mut self) -> Result<String> {
+        let mut rate_limit_retries = 0;
+        loop {
+            let response_format = self.structured_output.then(step_response_format);
+            let messages = self.request_messages();
+            let err = match self.chat_completion(messages, response_format).await {
+                Ok(content) => return Ok(content),
+                Err(err) => err,
+            };
+            match err.downcast_ref::<ApiError>().map(|e| e.kind) {
+                Some(ApiErrorKind::ContextLength) if self.trim_history() => {
+                    eprintln!(
+                        "[Brain] Context too long, trimmed history to {} messages.",
+                        self.conversation.len()
+                    );
+                }
+                Some(ApiErrorKind::RateLimit) if rate_limit_retries < RATE_LIMIT_RETRIES => {
+                    rate_limit_retries += 1;
+                    let delay = std::time::Duration::from_secs(1 << rate_limit_retries);
+                    eprintln!("[Brain] Rate limited, retrying in {:?}...", delay);
+                    tokio::time::sleep(delay).await;
+                }
+                _ => return Err(err),
+            }
+        }
+    }
+
+    /// The conversation as API messages, with any pending screenshot
+    /// attached to the latest one.
+    fn request_messages(&self) -> Vec<serde_json::Value> {
+        let mut messages: Vec<serde_json::Value> = self
             .conversation
             .iter()
             .map(|m| json!({"role": m.role, "content": m.content}))
             .collect();
+        if let (Some(jpeg), Some(last)) = (&self.pending_screenshot, messages.last_mut()) {
+            last["content"] = json!([
+                {"type": "text", "text": last["content"]},
+                {
+                    "type": "image_url",
+                    "image_url": {"url": format!("data:image/jpeg;base64,{}", jpeg)},
+                },
+            ]);
+        }
+        messages
+    }
 
-        // Check token limit helper (naive)
-        if messages.len() > 20 {
-            eprintln!(
-                "[Brain] Warning: Conversation history is long ({})",
-                messages.len()
+    /// Send a screenshot (base64 JPEG) with the next request, for pages the
+    /// DOM snapshot can't describe.
+    pub fn attach_screenshot(&mut self, jpeg_base64: String) {
+        if let Some(last) = self.conversation.last_mut() {
+            last.content.push_str(
+                "\n\nNOTE: The DOM snapshot is (nearly) empty, so a screenshot of the page is attached. \
+                 Use it to decide the next step.",
             );
         }
+        self.pending_screenshot = Some(jpeg_base64);
+    }
 
-        let response = self
-            .client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&json!({
-                "model": MODEL,
-                "messages": messages,
-                "temperature": 0.2,
-            }))
-            .send()
-            .await?;
+    /// Drop the oldest history to make room. Earlier tasks go first, then
+    /// the older half of the current one; the system prompt, the task and
+    /// the latest observation are always kept. Returns false once there is
+    /// nothing left to drop.
+    fn trim_history(&mut self) -> bool {
+        let task_start = latest_task(&self.conversation).unwrap_or(0);
+        if task_start > 1 {
+            self.conversation.drain(1..task_start);
+        } else {
+            let start = task_start + 1;
+            let removable = self.conversation.len().saturating_sub(start + 1);
+            if removable == 0 {
+                return false;
+            }
+            self.conversation
+                .drain(start..start + removable.div_ceil(2));
+        }
+        self.save_memory();
+        true
+    }
+
+    /// Tokens billed so far by this brain, including history summaries.
+    pub fn tokens_used(&self) -> u64 {
+        self.tokens_used
+    }
+
+    /// Send one chat-completions request and return the reply text.
+    async fn chat_completion(
+        &mut self,
+        messages: Vec<serde_json::Value>,
+        response_format: Option<serde_json::Value>,
+    ) -> Result<String> {
+        let request = self.client.post(&self.completions_url);
+        let request = if self.azure_auth {
+            request.header("api-key", &self.api_key)
+        } else {
+            request.header("Authorization", format!("Bearer {}", self.api_key))
+        };
+
+        let mut body = json!({
+            "model": MODEL,
+            "messages": messages,
+            "temperature": 0.2,
+        });
+        if let Some(format) = response_format {
+            body["response_format"] = format;
+        }
+
+        let response = request.json(&body).send().await?;
 
         let status = response.status();
         let json_resp: serde_json::Value = response.json().await?;
 
         if !status.is_success() {
-            let err_msg = json_resp["error"]["message"]
-                .as_str()
-                .unwrap_or("Unknown API error");
-            eprintln!("[Brain] API error ({}): {}", status, err_msg);
-            return Err(anyhow!("OpenAI API error ({}): {}", status, err_msg));
+            let err = ApiError::from_response(status.as_u16(), &json_resp);
+            eprintln!("[Brain] API error ({:?}): {}", err.kind, err);
+            return Err(err.into());
         }
 
+        self.tokens_used += json_resp["usage"]["total_tokens"].as_u64().unwrap_or(0);
+
         let content = json_resp["choices"][0]["message"]["content"]
             .as_str()
             .ok_or_else(|| {
@@ -161,33 +789,161 @@ impl Brain {
                 anyhow!("No content in LLM response: {}", json_resp)
             })?;
 
-        eprintln!("[Brain] LLM says: {}", content);
+        Ok(content.to_string())
+    }
+}
 
-        // Record assistant response in conversation history
-        self.conversation.push(ChatMessage {
-            role: "assistant".to_string(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reply filling every field in `fields` with a placeholder value.
+    fn sample_reply(action: &str, fields: &[(&str, &str)]) -> serde_json::Value {
+        let mut step = json!({"action": action});
+        for (name, ty) in fields {
+            step[*name] = match *ty {
+                "integer" => json!(1),
+                "boolean" => json!(true),
+                "string[]" => json!(["x"]),
+                t if t.ends_with('?') => json!(null),
+                _ => json!("x"),
+            };
+        }
+        step
+    }
+
+    #[test]
+    fn summary_keeps_current_task() {
+        let msg = |role: &str, content: &str| ChatMessage {
+            role: role.to_string(),
             content: content.to_string(),
-        });
-        self.save_memory(); // Save after assistant reply
-
-        // Strip possible markdown fences the LLM might add
-        let cleaned = content
-            .trim()
-            .trim_start_matches("```json")
-            .trim_start_matches("```")
-            .trim_end_matches("```")
-            .trim();
-
-        eprintln!("[Brain] Cleaned JSON: {}", cleaned);
-
-        let step: Step = match serde_json::from_str(cleaned) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("[Brain] JSON Parse Error: {}. Content: {}", e, cleaned);
-                return Err(anyhow!("Failed to parse LLM response: {}", e));
-            }
         };
+        let mut conversation = vec![
+            msg("system", "prompt"),
+            msg("user", "Task: old"),
+            msg("assistant", "{}"),
+            msg("user", "Task: current"),
+            msg("assistant", "{}"),
+            msg("user", "Page URL: a"),
+            msg("assistant", "{}"),
+        ];
+        let folded = messages_to_summarize(&conversation, 5);
+        assert_eq!(folded.len(), 3);
+        assert!(folded.iter().all(|m| m.content != "Task: current"));
+
+        replace_with_summary(&mut conversation, 5, "did things");
+        let contents: Vec<&str> = conversation.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "prompt",
+                "Task: current",
+                "Context so far: did things",
+                "Page URL: a",
+                "{}"
+            ]
+        );
+    }
+
+    #[test]
+    fn clears_extracted_content_but_keeps_labels() {
+        let observation = "Page URL: https://a.test\n\nDOM:\n[e0] link\n\nExtracted [price]: $10\n\nExtracted [rows]: a\nb";
+        assert_eq!(
+            clear_extracted_text(observation).unwrap(),
+            "Page URL: https://a.test\n\nDOM:\n[e0] link\n\nExtracted [price]: (cleared)\n\nExtracted [rows]: (cleared)"
+        );
+        assert!(clear_extracted_text("Page URL: https://a.test").is_none());
+    }
+
+    #[test]
+    fn clearing_extractions_keeps_trailing_notes() {
+        let observation =
+            "DOM:\n\n\nExtracted [price]: $10\n\nNOTE: The DOM snapshot is (nearly) empty";
+        assert_eq!(
+            clear_extracted_text(observation).unwrap(),
+            "DOM:\n\n\nExtracted [price]: (cleared)\n\nNOTE: The DOM snapshot is (nearly) empty"
+        );
+    }
+
+    #[test]
+    fn classifies_api_errors() {
+        let kind = |status, body: serde_json::Value| ApiError::from_response(status, &body).kind;
+        assert_eq!(
+            kind(
+                400,
+                json!({"error": {"message": "This model's maximum context length is 128000 tokens", "code": "context_length_exceeded"}})
+            ),
+            ApiErrorKind::ContextLength
+        );
+        assert_eq!(
+            kind(
+                401,
+                json!({"error": {"message": "Incorrect API key", "code": "invalid_api_key"}})
+            ),
+            ApiErrorKind::Auth
+        );
+        assert_eq!(
+            kind(
+                429,
+                json!({"error": {"message": "Rate limit reached", "code": "rate_limit_exceeded"}})
+            ),
+            ApiErrorKind::RateLimit
+        );
+        assert_eq!(
+            kind(
+                429,
+                json!({"error": {"message": "Quota exceeded", "code": "insufficient_quota"}})
+            ),
+            ApiErrorKind::Other
+        );
+        assert_eq!(kind(500, json!({})), ApiErrorKind::Other);
+    }
+
+    #[test]
+    fn schema_fields_match_step_variants() {
+        for (action, fields) in STEP_FIELDS {
+            let reply = sample_reply(action, fields);
+            let step = parse_step(&reply.to_string());
+            assert!(step.is_ok(), "{} does not parse: {:?}", action, step);
+        }
+    }
+
+    #[test]
+    fn every_prompt_action_is_in_schema() {
+        for line in SYSTEM_PROMPT.lines() {
+            let Some(rest) = line.split("{\"action\":\"").nth(1) else {
+                continue;
+            };
+            let action = rest.split('"').next().unwrap();
+            assert!(
+                STEP_FIELDS.iter().any(|(a, _)| *a == action),
+                "{} missing from STEP_FIELDS",
+                action
+            );
+        }
+    }
+
+    #[test]
+    fn schema_lists_every_action() {
+        let format = step_response_format();
+        let variants = format["json_schema"]["schema"]["properties"]["step"]["anyOf"]
+            .as_array()
+            .unwrap();
+        assert_eq!(variants.len(), STEP_FIELDS.len());
+    }
+
+    #[test]
+    fn unwraps_structured_reply() {
+        let decision = parse_structured_step(
+            r#"{"step":{"action":"Done","summary":"ok"},"reason":"Answer is on the page"}"#,
+        )
+        .unwrap();
+        assert!(matches!(decision.step, Step::Done { summary } if summary == "ok"));
+        assert_eq!(decision.reason.as_deref(), Some("Answer is on the page"));
+    }
 
-        Ok(step)
+    #[test]
+    fn rejects_structured_reply_without_step() {
+        assert!(parse_structured_step(r#"{"action":"NewTab"}"#).is_err());
     }
 }
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use headless_chrome::Tab;
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 
 use crate::types::DOM_SNAPSHOT_MAX_CHARS;
 
@@ -13,8 +14,16 @@ use crate::types::DOM_SNAPSHOT_MAX_CHARS;
 ///   3. Assigns sequential IDs [e0], [e1], ... to interactive elements
 ///      (a, button, input, textarea, select) via data-eid attributes.
 ///   4. Emits a compact one-line-per-element text representation.
+///   5. Never includes the value of password inputs.
+///   6. Labels form fields with their associated text: aria-labelledby,
+///      a `<label for>` or wrapping `<label>`, aria-label, or failing those
+///      a preceding text-only sibling.
+///   7. With `opts.coords`, appends each interactive element's bounding box
+///      as `@(x,y,width,height)` in CSS pixels.
+///
+/// It is a function expression; `capture_dom_snapshot` calls it with the options.
 const SNAPSHOT_JS: &str = r#"
-(() => {
+((opts) => {
   const SKIP = new Set(['SCRIPT','STYLE','NOSCRIPT','SVG','LINK']);
   let id = 0;
   const lines = [];
@@ -26,29 +35,59 @@ const SNAPSHOT_JS: &str = r#"
     return s.display !== 'none' && s.visibility !== 'hidden' && s.opacity !== '0';
   }
 
+  function labelOf(el) {
+    let text = '';
+    const ids = el.getAttribute('aria-labelledby');
+    if (ids) {
+      text = ids.split(/\s+/).map(i => {
+        const l = document.getElementById(i);
+        return l ? l.textContent : '';
+      }).join(' ');
+    }
+    if (!text.trim() && el.labels && el.labels.length) text = el.labels[0].textContent;
+    if (!text.trim()) text = el.getAttribute('aria-label') || '';
+    if (!text.trim()) {
+      const prev = el.previousElementSibling;
+      if (prev && prev.children.length === 0) text = prev.textContent;
+    }
+    return text.trim().replace(/\s+/g, ' ').slice(0, 40);
+  }
+
   function walk(node, depth) {
     if (depth > 15) return;
     for (const child of node.children) {
       if (SKIP.has(child.tagName)) continue;
       if (!isVisible(child)) continue;
       const tag = child.tagName.toLowerCase();
-      const interactive = ['a','button','input','textarea','select'].includes(tag);
+      // Only the root of a rich-text editor, not every node inside it
+      const editable = child.isContentEditable && !(child.parentElement && child.parentElement.isContentEditable);
+      const interactive = editable || ['a','button','input','textarea','select'].includes(tag);
 
       if (interactive) {
         const eid = '[e' + (id++) + ']';
         child.setAttribute('data-eid', eid);
         let desc = '';
-        if (tag === 'a') {
+        if (editable) {
+          desc = eid + ' editable "' + (child.innerText||'').trim().slice(0,30) + '"';
+        } else if (tag === 'a') {
           desc = eid + ' link "' + (child.textContent||'').trim().slice(0,60) + '"';
         } else if (tag === 'input' || tag === 'textarea') {
-          desc = eid + ' ' + tag + ' type=' + (child.type||'text') + ' placeholder="' + (child.placeholder||'') + '"';
+          desc = eid + ' ' + tag + ' type=' + (child.type||'text');
+          const label = labelOf(child);
+          if (label) desc += ' label="' + label + '"';
+          desc += ' placeholder="' + (child.placeholder||'') + '"';
           if (child.name) desc += ' name=' + child.name;
-          if (child.value) desc += ' value="' + child.value.slice(0,30) + '"';
+          if (child.value && child.type !== 'password') desc += ' value="' + child.value.slice(0,30) + '"';
         } else if (tag === 'button') {
           desc = eid + ' button "' + (child.textContent||'').trim().slice(0,60) + '"';
         } else if (tag === 'select') {
-          const opts = [...child.options].map(o => o.text.trim().slice(0,20)).join('|');
-          desc = eid + ' select [' + opts + ']';
+          const choices = [...child.options].map(o => o.text.trim().slice(0,20)).join('|');
+          const label = labelOf(child);
+          desc = eid + ' select' + (label ? ' label="' + label + '"' : '') + ' [' + choices + ']';
+        }
+        if (desc && opts.coords) {
+          const r = child.getBoundingClientRect();
+          desc += ' @(' + [r.x, r.y, r.width, r.height].map(Math.round).join(',') + ')';
         }
         if (desc && !seen.has(desc)) {
           seen.add(desc);
@@ -70,16 +109,117 @@ const SNAPSHOT_JS: &str = r#"
 
   walk(document.body, 0);
   return lines.join('\n');
+})
+"#;
+
+/// How many recent snapshots `SNAPSHOT_CACHE` keeps.
+const SNAPSHOT_CACHE_SIZE: usize = 8;
+
+/// Recently captured snapshots, most recent last.
+static SNAPSHOT_CACHE: Mutex<SnapshotCache> = Mutex::new(SnapshotCache::new());
+
+/// Small LRU of raw snapshots keyed by tab and page fingerprint.
+struct SnapshotCache {
+    entries: VecDeque<(String, String)>,
+}
+
+impl SnapshotCache {
+    const fn new() -> Self {
+        SnapshotCache {
+            entries: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<String> {
+        let pos = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(pos)?;
+        let snapshot = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(snapshot)
+    }
+
+    fn put(&mut self, key: String, snapshot: String) {
+        self.entries.retain(|(k, _)| *k != key);
+        if self.entries.len() >= SNAPSHOT_CACHE_SIZE {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((key, snapshot));
+    }
+}
+
+/// Installs (once per document) a revision counter that DOM mutations, form
+/// input and finished CSS transitions/animations bump, then returns
+/// `<document id>#<revision>#<scroll>#<viewport>#<form hash>#<url>`.
+///
+/// The snapshot's own data-eid tags don't count as changes, and each new
+/// document gets a fresh random id, so a reload never reuses a key. Scroll
+/// position, viewport size and a hash of form values are read on every call
+/// since scrolling, resizing and script `el.value = ...` writes fire no
+/// mutation.
+const FINGERPRINT_JS: &str = r#"
+(() => {
+  if (window.__agentDoc === undefined) {
+    window.__agentDoc = Math.random().toString(36).slice(2);
+    window.__agentRev = 0;
+    const bump = () => { window.__agentRev++; };
+    new MutationObserver(records => {
+      if (records.some(r => r.attributeName !== 'data-eid')) bump();
+    }).observe(document.documentElement, {
+      subtree: true, childList: true, attributes: true, characterData: true
+    });
+    for (const type of ['input', 'change', 'transitionend', 'animationend']) {
+      document.addEventListener(type, bump, true);
+    }
+  }
+  let form = 0;
+  for (const el of document.querySelectorAll('input, textarea, select')) {
+    const v = el.type === 'checkbox' || el.type === 'radio' ? String(el.checked) : String(el.value);
+    for (let i = 0; i < v.length; i++) form = (form * 31 + v.charCodeAt(i)) | 0;
+    form = (form * 31 + 1) | 0;
+  }
+  return [
+    window.__agentDoc, window.__agentRev,
+    Math.round(scrollX) + ',' + Math.round(scrollY),
+    innerWidth + 'x' + innerHeight,
+    form, location.href,
+  ].join('#');
 })()
 "#;
 
+/// Identity of the tab's current document and its state, used as the
+/// snapshot cache key. Any change to the page gives a new fingerprint.
+fn page_fingerprint(tab: &Arc<Tab>) -> Option<String> {
+    let page = tab.evaluate(FINGERPRINT_JS, false).ok()?.value?;
+    Some(format!("{}#{}", tab.get_target_id(), page.as_str()?))
+}
+
 /// Capture a simplified DOM snapshot from the current page.
+///
+/// Reuses the last snapshot of an unchanged page instead of walking the DOM
+/// again. Coordinates change with scrolling, so SNAPSHOT_COORDS disables this.
 pub fn capture_dom_snapshot(tab: &Arc<Tab>) -> Result<String> {
-    let result = tab.evaluate(SNAPSHOT_JS, false)?;
-    let raw = result
-        .value
-        .and_then(|v| v.as_str().map(String::from))
-        .unwrap_or_default();
+    // Coordinates help vision-capable models but cost tokens, so they're opt-in
+    let coords = std::env::var("SNAPSHOT_COORDS").is_ok_and(|v| v == "1");
+    let fingerprint = if coords { None } else { page_fingerprint(tab) };
+
+    let cached = fingerprint
+        .as_deref()
+        .and_then(|key| SNAPSHOT_CACHE.lock().unwrap().get(key));
+    let raw = match cached {
+        Some(raw) => raw,
+        None => {
+            let options = serde_json::json!({ "coords": coords });
+            let result = tab.evaluate(&format!("({SNAPSHOT_JS})({options})"), false)?;
+            let raw = result
+                .value
+                .and_then(|v| v.as_str().map(String::from))
+                .unwrap_or_default();
+            if let Some(key) = fingerprint {
+                SNAPSHOT_CACHE.lock().unwrap().put(key, raw.clone());
+            }
+            raw
+        }
+    };
 
     if raw.len() > DOM_SNAPSHOT_MAX_CHARS {
         Ok(format!(
@@ -101,6 +241,57 @@ pub fn get_current_url(tab: &Arc<Tab>) -> Result<String> {
         .unwrap_or_else(|| "unknown".to_string()))
 }
 
+/// Match a URL against a `WaitForUrl` pattern. Without `*` the pattern is
+/// a substring; with it, a glob over the whole URL where `*` matches anything.
+pub fn url_matches(url: &str, pattern: &str) -> bool {
+    if !pattern.contains('*') {
+        return url.contains(pattern);
+    }
+
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !url.starts_with(first) || !url[first.len()..].ends_with(last) {
+        return false;
+    }
+    let mut rest = &url[first.len()..url.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Whether two URLs point at the same page, ignoring any fragment and a
+/// trailing slash.
+pub fn same_page(a: &str, b: &str) -> bool {
+    let normalize = |url: &str| {
+        let url = url.split('#').next().unwrap_or(url);
+        url.trim_end_matches('/').to_string()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Lowercased host of `url`, without userinfo, port or trailing dot.
+pub fn host_of(url: &str) -> Option<String> {
+    let rest = url.split_once("://")?.1;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?.trim_end_matches('.');
+    (!host.is_empty()).then(|| host.to_lowercase())
+}
+
+/// Whether `url`'s host is one of `blocked` or a subdomain of one.
+pub fn is_blocked(url: &str, blocked: &[String]) -> bool {
+    let Some(host) = host_of(url) else {
+        return false;
+    };
+    blocked
+        .iter()
+        .any(|d| host == *d || host.ends_with(&format!(".{}", d)))
+}
+
 /// Get the current page title.
 pub fn get_page_title(tab: &Arc<Tab>) -> Result<String> {
     let result = tab.evaluate("document.title", false)?;
@@ -109,3 +300,298 @@ pub fn get_page_title(tab: &Arc<Tab>) -> Result<String> {
         .and_then(|v| v.as_str().map(String::from))
         .unwrap_or_else(|| "untitled".to_string()))
 }
+
+/// Report scroll position and page height as JSON, so the Brain can tell
+/// whether scrolling would reveal more content.
+pub fn page_info(tab: &Arc<Tab>) -> Result<String> {
+    let result = tab.evaluate(
+        r#"JSON.stringify({
+            scrollY: Math.round(window.scrollY),
+            innerHeight: window.innerHeight,
+            scrollHeight: document.body.scrollHeight,
+            moreBelow: window.scrollY + window.innerHeight < document.body.scrollHeight - 1
+        })"#,
+        false,
+    )?;
+    Ok(result
+        .value
+        .and_then(|v| v.as_str().map(String::from))
+        .unwrap_or_default())
+}
+
+/// JS `(el, value) => void` that sets a form control's value the way React
+/// and Vue notice: through the prototype's native setter (their own
+/// per-instance setter swallows plain assignment), then `input`/`change`.
+/// Contenteditable elements have no value, so their text is replaced with
+/// `execCommand`, which rich-text editors handle like typing.
+const SET_VALUE_JS: &str = r#"(el, value) => {
+    if (el.isContentEditable) {
+        if (el.innerText.replace(/\n$/, '') === value) return;
+        el.focus();
+        const range = document.createRange();
+        range.selectNodeContents(el);
+        getSelection().removeAllRanges();
+        getSelection().addRange(range);
+        const ok = value ? document.execCommand('insertText', false, value)
+            : document.execCommand('delete');
+        if (!ok) {
+            el.textContent = value;
+            el.dispatchEvent(new Event('input', {bubbles: true}));
+        }
+        return;
+    }
+    if (!('value' in el) || el.value === value) return;
+    const proto = el instanceof HTMLTextAreaElement ? HTMLTextAreaElement.prototype
+        : el instanceof HTMLSelectElement ? HTMLSelectElement.prototype
+        : el instanceof HTMLInputElement ? HTMLInputElement.prototype
+        : null;
+    const setter = proto && Object.getOwnPropertyDescriptor(proto, 'value').set;
+    if (setter) setter.call(el, value); else el.value = value;
+    el.dispatchEvent(new Event('input', {bubbles: true}));
+    el.dispatchEvent(new Event('change', {bubbles: true}));
+}"#;
+
+/// Set the value of the element matching `selector` so framework-controlled
+/// inputs pick it up. Returns false if nothing matched.
+pub fn set_value(tab: &Arc<Tab>, selector: &str, value: &str) -> Result<bool> {
+    let js_sel = serde_json::to_string(selector)?;
+    let js_value = serde_json::to_string(value)?;
+    let result = tab.evaluate(
+        &format!(
+            "(() => {{ const el = document.querySelector({js_sel}); \
+             if (!el) return false; \
+             ({SET_VALUE_JS})(el, {js_value}); \
+             return true; }})()"
+        ),
+        false,
+    )?;
+    Ok(result.value.and_then(|v| v.as_bool()).unwrap_or(false))
+}
+
+/// Current value of the field matching `selector` (text for contenteditable
+/// elements). None if nothing matched.
+pub fn field_value(tab: &Arc<Tab>, selector: &str) -> Result<Option<String>> {
+    let js_sel = serde_json::to_string(selector)?;
+    let result = tab.evaluate(
+        &format!(
+            "(() => {{ const el = document.querySelector({js_sel}); \
+             if (!el) return null; \
+             return el.isContentEditable ? el.innerText.replace(/\\n$/, '') : String(el.value ?? ''); }})()"
+        ),
+        false,
+    )?;
+    Ok(result.value.and_then(|v| v.as_str().map(String::from)))
+}
+
+/// JavaScript function expression that finds the form control labelled
+/// `label`, or returns null.
+///
+/// Tries `<label>` text (exact, then contains) resolved through `for` or a
+/// nested control, then falls back to placeholder and aria-label.
+const FIND_BY_LABEL_JS: &str = r#"(label) => {
+    const norm = s => (s || '').replace(/\s+/g, ' ').trim().toLowerCase();
+    const want = norm(label);
+    const labels = [...document.querySelectorAll('label')];
+    const control = l => l.control
+        || (l.htmlFor && document.getElementById(l.htmlFor))
+        || l.querySelector('input, textarea, select');
+    for (const match of [t => t === want, t => t.includes(want)]) {
+        const l = labels.find(l => match(norm(l.textContent)) && control(l));
+        if (l) return control(l);
+    }
+    return [...document.querySelectorAll('input, textarea')].find(e =>
+        norm(e.placeholder) === want || norm(e.getAttribute('aria-label')) === want) || null;
+}"#;
+
+/// Find the form control labelled `label` (see `FIND_BY_LABEL_JS`) and focus
+/// it with its value cleared. Returns false if nothing matched.
+pub fn focus_by_label(tab: &Arc<Tab>, label: &str) -> Result<bool> {
+    let js_label = serde_json::to_string(label)?;
+    let result = tab.evaluate(
+        &format!(
+            r#"(() => {{
+            const el = ({FIND_BY_LABEL_JS})({js_label});
+            if (!el) return false;
+            el.focus();
+            ({SET_VALUE_JS})(el, '');
+            return true;
+        }})()"#
+        ),
+        false,
+    )?;
+    Ok(result.value.and_then(|v| v.as_bool()).unwrap_or(false))
+}
+
+/// Center the element matching `selector` in its scroll containers and the
+/// viewport, then give the page a moment to settle. Returns false if
+/// nothing matched.
+pub fn scroll_into_view(tab: &Arc<Tab>, selector: &str) -> Result<bool> {
+    let js_sel = serde_json::to_string(selector)?;
+    let result = tab.evaluate(
+        &format!(
+            "(() => {{ const el = document.querySelector({js_sel}); \
+             if (!el) return false; \
+             el.scrollIntoView({{block: 'center', inline: 'center'}}); \
+             return true; }})()"
+        ),
+        false,
+    )?;
+    let found = result.value.and_then(|v| v.as_bool()).unwrap_or(false);
+    if found {
+        std::thread::sleep(std::time::Duration::from_millis(200));
+    }
+    Ok(found)
+}
+
+/// JS that finds the topmost visible modal and clicks its close control.
+/// Returns "none" (no modal), "clicked", or "no-button" (modal without an
+/// obvious close control).
+const DISMISS_MODAL_JS: &str = r#"(() => {
+    const visible = el => el.getClientRects().length > 0
+        && getComputedStyle(el).visibility !== 'hidden';
+    const modals = [...document.querySelectorAll(
+        '[role=dialog], [role=alertdialog], [aria-modal=true], .modal')].filter(visible);
+    const modal = modals[modals.length - 1];
+    if (!modal) return 'none';
+    const labels = ['×', '✕', 'x', 'close', 'dismiss', 'no thanks', 'not now'];
+    const close = [...modal.querySelectorAll(
+        '[aria-label*=close i], [data-dismiss], [data-bs-dismiss], .close, button')]
+        .filter(visible)
+        .find(el => !el.matches('button') || el.matches('[aria-label*=close i], .close')
+            || labels.includes((el.textContent || '').trim().toLowerCase()));
+    if (!close) return 'no-button';
+    close.click();
+    return 'clicked';
+})()"#;
+
+/// Whether a `[role=dialog]`-style modal is currently visible.
+fn modal_open(tab: &Arc<Tab>) -> Result<bool> {
+    let result = tab.evaluate(
+        "[...document.querySelectorAll('[role=dialog], [role=alertdialog], [aria-modal=true], .modal')]\
+         .some(el => el.getClientRects().length > 0 && getComputedStyle(el).visibility !== 'hidden')",
+        false,
+    )?;
+    Ok(result.value.and_then(|v| v.as_bool()).unwrap_or(false))
+}
+
+/// Close the visible modal through its close button, or Escape if it has
+/// none, and check that it went away. Returns a note on what happened.
+pub fn dismiss_modal(tab: &Arc<Tab>) -> Result<String> {
+    let outcome = tab
+        .evaluate(DISMISS_MODAL_JS, false)?
+        .value
+        .and_then(|v| v.as_str().map(String::from))
+        .unwrap_or_default();
+    let how = match outcome.as_str() {
+        "none" => return Ok("No modal was open; nothing to dismiss.".to_string()),
+        "clicked" => "clicking its close button",
+        _ => {
+            tab.press_key("Escape")?;
+            "pressing Escape"
+        }
+    };
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    if modal_open(tab)? {
+        anyhow::bail!("A modal is still open after {}", how);
+    }
+    Ok(format!("Dismissed a modal by {}.", how))
+}
+
+/// Whether the control `focus_by_label` would pick for `label` is a password
+/// input, so typed text can be redacted.
+pub fn is_password_label(tab: &Arc<Tab>, label: &str) -> bool {
+    let Ok(js_label) = serde_json::to_string(label) else {
+        return false;
+    };
+    tab.evaluate(
+        &format!("(({FIND_BY_LABEL_JS})({js_label}) || {{}}).type === 'password'"),
+        false,
+    )
+    .ok()
+    .and_then(|r| r.value)
+    .and_then(|v| v.as_bool())
+    .unwrap_or(false)
+}
+
+/// Whether `selector` matches a password input, so typed text can be redacted.
+pub fn is_password_field(tab: &Arc<Tab>, selector: &str) -> bool {
+    let Ok(js_sel) = serde_json::to_string(selector) else {
+        return false;
+    };
+    tab.evaluate(
+        &format!("(document.querySelector({js_sel}) || {{}}).type === 'password'"),
+        false,
+    )
+    .ok()
+    .and_then(|r| r.value)
+    .and_then(|v| v.as_bool())
+    .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_pattern_without_star_is_substring() {
+        assert!(url_matches("https://app.test/dashboard?x=1", "/dashboard"));
+        assert!(!url_matches("https://app.test/login", "/dashboard"));
+    }
+
+    #[test]
+    fn url_pattern_with_star_is_glob() {
+        assert!(url_matches(
+            "https://app.test/dashboard",
+            "https://*/dashboard"
+        ));
+        assert!(url_matches("https://app.test/a/b/c", "*/a/*/c"));
+        assert!(!url_matches(
+            "https://app.test/dashboard/x",
+            "https://*/dashboard"
+        ));
+        assert!(!url_matches("http://app.test/dashboard", "https://*"));
+        assert!(!url_matches("https://ab", "https://ab*b"));
+    }
+
+    #[test]
+    fn same_page_ignores_slash_and_fragment() {
+        assert!(same_page(
+            "https://a.test/docs/",
+            "https://a.test/docs#intro"
+        ));
+        assert!(same_page("https://a.test", "https://a.test/"));
+        assert!(!same_page("https://a.test/docs?p=2", "https://a.test/docs"));
+    }
+
+    #[test]
+    fn blocks_domain_and_subdomains() {
+        let blocked = vec!["bank.com".to_string()];
+        assert!(is_blocked("https://bank.com/login", &blocked));
+        assert!(is_blocked("https://User@WWW.Bank.com:443/", &blocked));
+        assert!(!is_blocked("https://notbank.com/", &blocked));
+        assert!(!is_blocked("https://example.com/?next=bank.com", &blocked));
+        assert!(!is_blocked("about:blank", &blocked));
+    }
+
+    #[test]
+    fn snapshot_cache_hits_by_key() {
+        let mut cache = SnapshotCache::new();
+        cache.put("https://a/#10".into(), "a".into());
+        assert_eq!(cache.get("https://a/#10").as_deref(), Some("a"));
+        assert_eq!(cache.get("https://a/#11"), None);
+    }
+
+    #[test]
+    fn snapshot_cache_evicts_least_recently_used() {
+        let mut cache = SnapshotCache::new();
+        for i in 0..SNAPSHOT_CACHE_SIZE {
+            cache.put(i.to_string(), i.to_string());
+        }
+        // Touch the oldest so the second-oldest is evicted instead
+        assert!(cache.get("0").is_some());
+        cache.put("new".into(), "new".into());
+        assert!(cache.get("0").is_some());
+        assert_eq!(cache.get("1"), None);
+        assert_eq!(cache.entries.len(), SNAPSHOT_CACHE_SIZE);
+    }
+}
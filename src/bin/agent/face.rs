@@ -1,57 +1,186 @@
 use axum::Json;
 use axum::Router;
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::response::Html;
 use axum::response::sse::{Event, Sse};
 use axum::routing::{get, post};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 use std::sync::Arc;
-use tokio::sync::{broadcast, mpsc};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+use tokio::sync::{broadcast, mpsc, oneshot};
 use tokio_stream::StreamExt;
 use tokio_stream::wrappers::BroadcastStream;
 
 /// Events streamed to the browser via SSE.
-#[derive(Clone, Debug)]
+///
+/// Each variant's fields become the JSON `data` payload; the `type` tag
+/// matches the SSE event name.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum AgentEvent {
-    Step { number: usize, description: String },
-    StepError { message: String },
-    TaskComplete { summary: String },
-    TaskError { message: String },
+    Step {
+        number: usize,
+        /// Step budget for this run, so the UI can warn before it runs out.
+        limit: usize,
+        kind: &'static str,
+        description: String,
+        /// The LLM's one-line rationale, if it gave one.
+        reason: Option<String>,
+    },
+    StepProposed {
+        number: usize,
+        kind: &'static str,
+        description: String,
+        reason: Option<String>,
+    },
+    StepError {
+        message: String,
+    },
+    /// The browser was reset after too many consecutive step errors.
+    Recovered {
+        message: String,
+    },
+    Extracted {
+        label: String,
+        content: String,
+    },
+    TaskComplete {
+        summary: String,
+        session_id: String,
+        /// Wall-clock time the task took.
+        duration_ms: u64,
+        /// Steps run before `Done`.
+        steps: usize,
+    },
+    TaskError {
+        message: String,
+        session_id: String,
+    },
+    BrowserUnavailable {
+        message: String,
+    },
     Thinking,
     Ready,
 }
 
 impl AgentEvent {
-    fn to_sse_event(&self) -> Event {
+    fn name(&self) -> &'static str {
         match self {
-            AgentEvent::Step {
-                number,
-                description,
-            } => Event::default().event("step").data(format!(
-                "{{\"number\":{},\"description\":{}}}",
-                number,
-                serde_json::json!(description)
-            )),
-            AgentEvent::StepError { message } => Event::default()
-                .event("step_error")
-                .data(format!("{{\"message\":{}}}", serde_json::json!(message))),
-            AgentEvent::TaskComplete { summary } => Event::default()
-                .event("task_complete")
-                .data(format!("{{\"summary\":{}}}", serde_json::json!(summary))),
-            AgentEvent::TaskError { message } => Event::default()
-                .event("task_error")
-                .data(format!("{{\"message\":{}}}", serde_json::json!(message))),
-            AgentEvent::Thinking => Event::default().event("thinking").data("{}"),
-            AgentEvent::Ready => Event::default().event("ready").data("{}"),
+            AgentEvent::Step { .. } => "step",
+            AgentEvent::StepProposed { .. } => "step_proposed",
+            AgentEvent::StepError { .. } => "step_error",
+            AgentEvent::Recovered { .. } => "recovered",
+            AgentEvent::Extracted { .. } => "extracted",
+            AgentEvent::TaskComplete { .. } => "task_complete",
+            AgentEvent::TaskError { .. } => "task_error",
+            AgentEvent::BrowserUnavailable { .. } => "browser_unavailable",
+            AgentEvent::Thinking => "thinking",
+            AgentEvent::Ready => "ready",
+        }
+    }
+
+    fn to_sse_event(&self) -> Event {
+        Event::default()
+            .event(self.name())
+            .json_data(self)
+            .expect("AgentEvent always serializes to JSON")
+    }
+}
+
+/// Commands sent from the web UI to the agent loop.
+#[derive(Debug)]
+pub enum AgentCommand {
+    Run(String),
+    Resume,
+    Relaunch,
+    /// Run a saved macro by name, without the LLM.
+    Replay(String),
+}
+
+/// Holds the step waiting on a human decision when MANUAL_STEP=1.
+#[derive(Default)]
+pub struct ApprovalGate {
+    pending: std::sync::Mutex<Option<oneshot::Sender<bool>>>,
+}
+
+impl ApprovalGate {
+    /// Park the current step until /approve or /reject is posted.
+    pub async fn wait(&self) -> bool {
+        let (tx, rx) = oneshot::channel();
+        *self.pending.lock().unwrap() = Some(tx);
+        rx.await.unwrap_or(false)
+    }
+
+    /// Answer the pending step. Returns false if nothing was waiting.
+    fn resolve(&self, approved: bool) -> bool {
+        match self.pending.lock().unwrap().take() {
+            Some(tx) => tx.send(approved).is_ok(),
+            None => false,
         }
     }
 }
 
-#[derive(Clone)]
+/// Cumulative counters for this process, served at /stats.
+pub struct Stats {
+    started: Instant,
+    pub tasks: AtomicUsize,
+    pub steps: AtomicUsize,
+    pub tokens: AtomicU64,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Stats {
+            started: Instant::now(),
+            tasks: AtomicUsize::new(0),
+            steps: AtomicUsize::new(0),
+            tokens: AtomicU64::new(0),
+        }
+    }
+
+    fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "tasks": self.tasks.load(Ordering::Relaxed),
+            "steps": self.steps.load(Ordering::Relaxed),
+            "tokens": self.tokens.load(Ordering::Relaxed),
+            "uptime_secs": self.started.elapsed().as_secs(),
+        })
+    }
+}
+
 pub struct AppState {
-    pub cmd_tx: mpsc::Sender<String>,
+    pub cmd_tx: mpsc::Sender<AgentCommand>,
     pub event_tx: broadcast::Sender<AgentEvent>,
+    pub approvals: ApprovalGate,
+    /// Why Chrome couldn't be started, replayed to clients that connect later.
+    pub browser_error: std::sync::Mutex<Option<String>>,
+    pub stats: Stats,
+    /// Whether a task is running, so reconnecting clients can resync.
+    pub busy: AtomicBool,
+    /// Random id for this process, attached to task outcomes for log correlation.
+    pub session_id: String,
+}
+
+impl AppState {
+    /// Fresh channels and state. Used by the server, and on its own when
+    /// running a single task from the command line.
+    pub fn new(session_id: String) -> (Self, mpsc::Receiver<AgentCommand>) {
+        let (cmd_tx, cmd_rx) = mpsc::channel::<AgentCommand>(1);
+        let (event_tx, _) = broadcast::channel::<AgentEvent>(64);
+
+        let state = AppState {
+            cmd_tx,
+            event_tx,
+            approvals: ApprovalGate::default(),
+            browser_error: std::sync::Mutex::new(None),
+            stats: Stats::new(),
+            busy: AtomicBool::new(false),
+            session_id,
+        };
+        (state, cmd_rx)
+    }
 }
 
 #[derive(Deserialize)]
@@ -59,25 +188,32 @@ struct CommandPayload {
     command: String,
 }
 
-/// Start the web server on localhost:3000. Returns the shared channels.
-pub async fn start_server() -> (mpsc::Receiver<String>, broadcast::Sender<AgentEvent>) {
-    let (cmd_tx, cmd_rx) = mpsc::channel::<String>(1);
-    let (event_tx, _) = broadcast::channel::<AgentEvent>(64);
+#[derive(Deserialize)]
+struct ReplayQuery {
+    name: String,
+}
 
-    let state = Arc::new(AppState {
-        cmd_tx,
-        event_tx: event_tx.clone(),
-    });
+/// Start the web server on localhost:3000. Returns the command channel and shared state.
+pub async fn start_server(session_id: String) -> (mpsc::Receiver<AgentCommand>, Arc<AppState>) {
+    let (state, cmd_rx) = AppState::new(session_id);
+    let state = Arc::new(state);
 
     let app = Router::new()
         .route("/", get(index_handler))
         .route("/command", post(command_handler))
+        .route("/resume", post(resume_handler))
+        .route("/relaunch", post(relaunch_handler))
+        .route("/replay", post(replay_handler))
+        .route("/approve", post(approve_handler))
+        .route("/reject", post(reject_handler))
         .route("/events", get(sse_handler))
+        .route("/stats", get(stats_handler))
+        .route("/health", get(health_handler))
         .route(
             "/favicon.ico",
             get(|| async { axum::http::StatusCode::NO_CONTENT }),
         ) // Silence 404
-        .with_state(state);
+        .with_state(state.clone());
 
     // Try port 3000, fall back to 3001-3009 if in use
     let mut listener = None;
@@ -101,7 +237,7 @@ pub async fn start_server() -> (mpsc::Receiver<String>, broadcast::Sender<AgentE
         axum::serve(listener, app).await.unwrap();
     });
 
-    (cmd_rx, event_tx)
+    (cmd_rx, state)
 }
 
 async fn index_handler() -> Html<&'static str> {
@@ -114,19 +250,78 @@ async fn command_handler(
     Json(payload): Json<CommandPayload>,
 ) -> &'static str {
     eprintln!("[Web] POST /command: {}", payload.command);
-    let _ = state.cmd_tx.send(payload.command).await;
+    let _ = state.cmd_tx.send(AgentCommand::Run(payload.command)).await;
+    "ok"
+}
+
+async fn resume_handler(State(state): State<Arc<AppState>>) -> &'static str {
+    eprintln!("[Web] POST /resume");
+    let _ = state.cmd_tx.send(AgentCommand::Resume).await;
+    "ok"
+}
+
+async fn relaunch_handler(State(state): State<Arc<AppState>>) -> &'static str {
+    eprintln!("[Web] POST /relaunch");
+    let _ = state.cmd_tx.send(AgentCommand::Relaunch).await;
     "ok"
 }
 
+async fn replay_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReplayQuery>,
+) -> &'static str {
+    eprintln!("[Web] POST /replay: {}", query.name);
+    let _ = state.cmd_tx.send(AgentCommand::Replay(query.name)).await;
+    "ok"
+}
+
+async fn approve_handler(State(state): State<Arc<AppState>>) -> &'static str {
+    eprintln!("[Web] POST /approve");
+    if state.approvals.resolve(true) {
+        "ok"
+    } else {
+        "no step pending"
+    }
+}
+
+async fn reject_handler(State(state): State<Arc<AppState>>) -> &'static str {
+    eprintln!("[Web] POST /reject");
+    if state.approvals.resolve(false) {
+        "ok"
+    } else {
+        "no step pending"
+    }
+}
+
+async fn stats_handler(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(state.stats.snapshot())
+}
+
+async fn health_handler(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "ok": true,
+        "session_id": state.session_id,
+        "busy": state.busy.load(Ordering::Relaxed),
+        "browser_error": state.browser_error.lock().unwrap().clone(),
+    }))
+}
+
 async fn sse_handler(
     State(state): State<Arc<AppState>>,
 ) -> Sse<impl tokio_stream::Stream<Item = Result<Event, Infallible>>> {
     let rx = state.event_tx.subscribe();
-    let stream =
-        BroadcastStream::new(rx).filter_map(|result: Result<AgentEvent, _>| match result {
-            Ok(event) => Some(Ok::<_, Infallible>(event.to_sse_event())),
-            Err(_) => None,
-        });
+
+    // Late joiners still need to see that the browser is down
+    let initial = state
+        .browser_error
+        .lock()
+        .unwrap()
+        .clone()
+        .map(|message| AgentEvent::BrowserUnavailable { message });
+
+    let stream = tokio_stream::iter(initial)
+        .chain(BroadcastStream::new(rx).filter_map(Result::ok))
+        .map(|event| Ok::<_, Infallible>(event.to_sse_event()));
     Sse::new(stream)
 }
 
@@ -165,6 +360,10 @@ const INDEX_HTML: &str = r##"<!DOCTYPE html>
     animation: pulse 2s infinite;
   }
   header .dot.busy { background: #f59e0b; }
+  header #counter { margin-left: auto; font-size: 13px; color: #888; }
+  header #counter.near-limit { color: #f59e0b; }
+  header #copy { padding: 6px 12px; font-size: 13px; }
+  header .dot.offline { background: #ef4444; animation: none; }
   @keyframes pulse {
     0%, 100% { opacity: 1; }
     50% { opacity: 0.4; }
@@ -214,6 +413,17 @@ const INDEX_HTML: &str = r##"<!DOCTYPE html>
     font-weight: 700;
     margin-right: 8px;
   }
+  .entry.step.navigate { border-left-color: #a855f7; }
+  .entry.step.click { border-left-color: #3b82f6; }
+  .entry.step.input { border-left-color: #06b6d4; }
+  .entry.step.extract { border-left-color: #22c55e; }
+  .entry.step.wait { border-left-color: #f59e0b; }
+  .entry.step .reason {
+    color: #888;
+    font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif;
+    font-size: 12px;
+    margin-top: 4px;
+  }
   .entry.error {
     background: #1a0a0a;
     border-left: 3px solid #ef4444;
@@ -260,25 +470,80 @@ const INDEX_HTML: &str = r##"<!DOCTYPE html>
   }
   button:hover { background: #4f46e5; }
   button:disabled { background: #333; cursor: not-allowed; }
+  .entry.step.proposed { border-left-style: dashed; opacity: 0.85; }
+  #banner {
+    display: none;
+    align-items: center;
+    justify-content: space-between;
+    gap: 12px;
+    padding: 10px 14px;
+    border-radius: 8px;
+    background: #1a0a0a;
+    border: 1px solid #ef4444;
+    color: #fca5a5;
+    font-size: 14px;
+  }
+  #conn {
+    display: none;
+    padding: 8px 14px;
+    border-radius: 8px;
+    background: #1a1400;
+    border: 1px solid #f59e0b;
+    color: #fcd34d;
+    font-size: 14px;
+  }
+  #approval {
+    display: none;
+    gap: 8px;
+    justify-content: flex-end;
+  }
+  #approval .reject { background: #7f1d1d; }
+  #approval .reject:hover { background: #991b1b; }
+  #stats {
+    font-size: 12px;
+    color: #666;
+    text-align: center;
+  }
 </style>
 </head>
 <body>
   <header>
     <div class="dot" id="status-dot"></div>
     <h1>AI Browser Agent</h1>
+    <span id="counter"></span>
+    <button id="copy" onclick="copyExtracted()" disabled>Copy all as JSON</button>
   </header>
   <div class="main">
+    <div id="conn">Connection lost, reconnecting…</div>
+    <div id="banner">
+      <span id="banner-text"></span>
+      <button onclick="relaunch()">Retry</button>
+    </div>
     <div id="log"></div>
+    <div id="approval">
+      <button class="reject" onclick="decide(false)">Reject</button>
+      <button onclick="decide(true)">Approve</button>
+    </div>
     <div class="input-area">
       <input type="text" id="cmd" placeholder="Tell the agent what to do..." autofocus />
       <button id="send" onclick="send()">Send</button>
     </div>
+    <div id="stats"></div>
   </div>
 <script>
   const log = document.getElementById('log');
   const cmd = document.getElementById('cmd');
   const sendBtn = document.getElementById('send');
   const dot = document.getElementById('status-dot');
+  const approval = document.getElementById('approval');
+  const banner = document.getElementById('banner');
+  const bannerText = document.getElementById('banner-text');
+  const stats = document.getElementById('stats');
+  const conn = document.getElementById('conn');
+  const copyBtn = document.getElementById('copy');
+  const counter = document.getElementById('counter');
+  // Everything extracted during the current task, for "Copy all as JSON"
+  let extracted = [];
   let busy = false;
 
   function addEntry(cls, html) {
@@ -301,6 +566,9 @@ const INDEX_HTML: &str = r##"<!DOCTYPE html>
     const text = cmd.value.trim();
     if (!text || busy) return;
     cmd.value = '';
+    extracted = [];
+    copyBtn.disabled = true;
+    copyBtn.textContent = 'Copy all as JSON';
     addEntry('user', '<strong>You:</strong> ' + text.replace(/</g,'&lt;'));
     setBusy(true);
     await fetch('/command', {
@@ -314,40 +582,163 @@ const INDEX_HTML: &str = r##"<!DOCTYPE html>
     if (e.key === 'Enter') send();
   });
 
-  const es = new EventSource('/events');
+  // Re-created with backoff if the browser gives up reconnecting on its own
+  const listeners = {};
+  let es = null;
+  let retryDelay = 1000;
+
+  function on(name, fn) {
+    listeners[name] = fn;
+    es.addEventListener(name, fn);
+  }
 
-  es.addEventListener('step', e => {
+  function connect() {
+    es = new EventSource('/events');
+    for (const name in listeners) es.addEventListener(name, listeners[name]);
+    es.onopen = () => {
+      retryDelay = 1000;
+      conn.style.display = 'none';
+      resync();
+    };
+    es.onerror = () => {
+      conn.style.display = 'block';
+      dot.className = 'dot offline';
+      if (es.readyState === EventSource.CLOSED) {
+        setTimeout(connect, retryDelay);
+        retryDelay = Math.min(retryDelay * 2, 30000);
+      }
+    };
+  }
+
+  // Events sent while disconnected are lost, so ask the server where it is
+  async function resync() {
+    const h = await (await fetch('/health')).json();
+    setBusy(h.busy);
+    refreshStats();
+  }
+
+  connect();
+
+  const STEP_ICONS = {navigate: '🌐', click: '👆', input: '⌨️', extract: '📄', wait: '⏳'};
+
+  function reasonHtml(d) {
+    return d.reason ? '<div class="reason">' + d.reason.replace(/</g,'&lt;') + '</div>' : '';
+  }
+
+  on('step', e => {
     const d = JSON.parse(e.data);
-    addEntry('step', '<span class="num">Step ' + d.number + '</span>' + d.description.replace(/</g,'&lt;'));
+    counter.textContent = 'Step ' + d.number + '/' + d.limit;
+    counter.className = d.number >= d.limit * 0.8 ? 'near-limit' : '';
+    const icon = STEP_ICONS[d.kind] ? STEP_ICONS[d.kind] + ' ' : '';
+    addEntry('step ' + d.kind, '<span class="num">' + icon + 'Step ' + d.number + '</span>' + d.description.replace(/</g,'&lt;') + reasonHtml(d));
   });
 
-  es.addEventListener('step_error', e => {
+  on('step_proposed', e => {
+    const d = JSON.parse(e.data);
+    addEntry('step proposed ' + d.kind, '<span class="num">Proposed step ' + d.number + '</span>' + d.description.replace(/</g,'&lt;') + reasonHtml(d));
+    approval.style.display = 'flex';
+  });
+
+  async function decide(approved) {
+    approval.style.display = 'none';
+    await fetch(approved ? '/approve' : '/reject', {method: 'POST'});
+  }
+
+  on('extracted', e => {
+    const d = JSON.parse(e.data);
+    extracted.push({label: d.label, content: d.content});
+    copyBtn.disabled = false;
+    copyBtn.textContent = 'Copy all as JSON (' + extracted.length + ')';
+  });
+
+  async function copyExtracted() {
+    await navigator.clipboard.writeText(JSON.stringify(extracted, null, 2));
+    copyBtn.textContent = 'Copied ' + extracted.length;
+  }
+
+  on('step_error', e => {
     const d = JSON.parse(e.data);
     addEntry('error', '<strong>Error:</strong> ' + d.message.replace(/</g,'&lt;'));
   });
 
-  es.addEventListener('task_complete', e => {
+  on('recovered', e => {
+    const d = JSON.parse(e.data);
+    addEntry('error', '<strong>Recovered:</strong> ' + d.message.replace(/</g,'&lt;'));
+  });
+
+  on('task_complete', e => {
     const d = JSON.parse(e.data);
-    addEntry('done', '<strong>Done:</strong> ' + d.summary.replace(/</g,'&lt;'));
+    const took = 'Done in ' + (d.duration_ms / 1000).toFixed(1) + 's (' + d.steps + ' steps):';
+    addEntry('done', '<strong>' + took + '</strong> ' + d.summary.replace(/</g,'&lt;'));
     setBusy(false);
   });
 
-  es.addEventListener('task_error', e => {
+  on('task_error', e => {
     const d = JSON.parse(e.data);
     addEntry('error', '<strong>Task failed:</strong> ' + d.message.replace(/</g,'&lt;'));
     setBusy(false);
   });
 
-  es.addEventListener('thinking', () => {
+  on('browser_unavailable', e => {
+    const d = JSON.parse(e.data);
+    bannerText.textContent = 'Browser unavailable: ' + d.message;
+    banner.style.display = 'flex';
+    setBusy(false);
+  });
+
+  async function relaunch() {
+    bannerText.textContent = 'Relaunching browser...';
+    await fetch('/relaunch', {method: 'POST'});
+  }
+
+  on('thinking', () => {
     addEntry('thinking', 'Thinking...');
   });
 
-  es.addEventListener('ready', () => {
+  on('ready', () => {
+    banner.style.display = 'none';
     setBusy(false);
+    refreshStats();
   });
 
+  async function refreshStats() {
+    const s = await (await fetch('/stats')).json();
+    const mins = Math.floor(s.uptime_secs / 60);
+    stats.textContent = s.tasks + ' tasks · ' + s.steps + ' steps · ' +
+      s.tokens + ' tokens · up ' + mins + 'm';
+  }
+
   addEntry('done', 'Agent ready. Type a command to begin.');
 </script>
 </body>
 </html>
 "##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_payload_keeps_client_fields() {
+        let event = AgentEvent::Step {
+            number: 3,
+            limit: 25,
+            kind: "click",
+            description: "Click \"<b>\"".to_string(),
+            reason: None,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["number"], 3);
+        assert_eq!(json["limit"], 25);
+        assert_eq!(json["kind"], "click");
+        assert_eq!(json["description"], "Click \"<b>\"");
+        assert_eq!(json["type"], event.name());
+        assert!(json["reason"].is_null());
+    }
+
+    #[test]
+    fn unit_events_serialize_to_objects() {
+        let json = serde_json::to_value(AgentEvent::Ready).unwrap();
+        assert_eq!(json, serde_json::json!({"type": "ready"}));
+    }
+}
@@ -1,19 +1,124 @@
 use anyhow::Result;
+use headless_chrome::protocol::cdp::Page;
 use headless_chrome::{Browser, LaunchOptions, Tab};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long Chrome may sit idle before headless_chrome gives up on it,
+/// from IDLE_TIMEOUT_SECS (default 300). Slow LLM replies can leave the
+/// browser idle for a while between steps.
+fn idle_timeout() -> Duration {
+    let secs = std::env::var("IDLE_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(300);
+    Duration::from_secs(secs)
+}
+
+/// Connect to a Chrome already listening on port 9222.
+///
+/// A freshly started Chrome may not be accepting connections yet, so retry
+/// ATTACH_RETRIES times (default 3), ATTACH_RETRY_DELAY_MS apart (default 500).
+fn attach_to_existing(idle_timeout: Duration) -> Option<Browser> {
+    let retries: u32 = std::env::var("ATTACH_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3);
+    let delay = Duration::from_millis(
+        std::env::var("ATTACH_RETRY_DELAY_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(500),
+    );
+
+    for attempt in 0..=retries {
+        match Browser::connect_with_timeout("http://127.0.0.1:9222".to_string(), idle_timeout) {
+            Ok(browser) => return Some(browser),
+            Err(e) if attempt < retries => {
+                eprintln!(
+                    "[Hands] Attach attempt {} failed ({}), retrying in {:?}...",
+                    attempt + 1,
+                    e,
+                    delay
+                );
+                std::thread::sleep(delay);
+            }
+            Err(_) => {}
+        }
+    }
+    None
+}
 
 /// Persistent browser session. Created once, reused for all tasks.
 pub struct BrowserSession {
-    _browser: Browser,
+    /// None once `close` has shut the browser down.
+    browser: Option<Browser>,
     pub tab: Arc<Tab>,
+    /// The tab the current task started in, so popups can be backed out of.
+    home_tab: Arc<Tab>,
+    pub downloads: Arc<Downloads>,
+    setup: TabSetup,
+    /// Pages visited during the current task as (title, url), oldest first.
+    history: Vec<(String, String)>,
+}
+
+/// Configuration applied to every tab the session drives.
+struct TabSetup {
+    downloads_dir: PathBuf,
+    /// Credentials answered to proxy auth challenges.
+    proxy_auth: Option<(String, String)>,
+    /// Hide common automation fingerprints (STEALTH=1).
+    stealth: bool,
+    /// USER_AGENT override; Chrome's own UA when unset.
+    user_agent: Option<String>,
+    /// Collect console errors for the Brain (CAPTURE_CONSOLE=1).
+    capture_console: bool,
+}
+
+impl TabSetup {
+    fn apply(&self, tab: &Tab) {
+        allow_downloads(tab, &self.downloads_dir);
+        track_status(tab);
+        authenticate_proxy(tab, self.proxy_auth.as_ref());
+        if self.stealth {
+            apply_stealth(tab);
+        }
+        if self.capture_console {
+            track_console(tab);
+        }
+        if let Some(ua) = &self.user_agent
+            && let Err(e) = tab.set_user_agent(ua, None, None)
+        {
+            eprintln!("[Hands] Warning: Could not set user agent: {}", e);
+        }
+    }
 }
 
 impl BrowserSession {
     pub fn launch() -> Result<Self> {
+        let downloads = Arc::new(Downloads::new(std::env::current_dir()?.join("downloads"))?);
+        let proxy = std::env::var("BROWSER_PROXY")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(|v| parse_proxy(&v));
+        let stealth = std::env::var("STEALTH").is_ok_and(|v| v == "1");
+        if stealth {
+            eprintln!("[Hands] Stealth mode enabled.");
+        }
+        let user_agent = std::env::var("USER_AGENT").ok().filter(|v| !v.is_empty());
+        let capture_console = std::env::var("CAPTURE_CONSOLE").is_ok_and(|v| v == "1");
+        if let Some(ua) = &user_agent {
+            eprintln!("[Hands] User agent: {}", ua);
+        }
+
+        let idle_timeout = idle_timeout();
+        eprintln!("[Hands] Idle browser timeout: {}s", idle_timeout.as_secs());
+
         // 1. Try to connect to existing Chrome (Attach Mode)
         eprintln!("[Hands] 🔗 Attempting to attach to existing Chrome on port 9222...");
-        if let Ok(browser) = Browser::connect("http://127.0.0.1:9222".to_string()) {
+        if let Some(browser) = attach_to_existing(idle_timeout) {
             eprintln!("[Hands] ✅ Attached to existing Chrome!");
 
             // get_tabs() returns Arc<Mutex<Vec<Arc<Tab>>>>
@@ -28,10 +133,28 @@ impl BrowserSession {
                     browser.new_tab()?
                 }
             };
+            let setup = TabSetup {
+                downloads_dir: downloads.dir.clone(),
+                proxy_auth: None,
+                stealth,
+                user_agent,
+                capture_console,
+            };
+            setup.apply(&tab);
+            grant_configured_permissions(&tab);
+            if proxy.is_some() {
+                eprintln!(
+                    "[Hands] Warning: BROWSER_PROXY is ignored when attaching to a running Chrome."
+                );
+            }
 
             return Ok(Self {
-                _browser: browser,
+                browser: Some(browser),
+                home_tab: tab.clone(),
                 tab,
+                downloads,
+                setup,
+                history: Vec::new(),
             });
         }
 
@@ -61,21 +184,33 @@ impl BrowserSession {
             eprintln!("[Hands] Using existing persistent shadow profile.");
         }
 
+        let mut args = vec![
+            std::ffi::OsStr::new("--no-first-run"),
+            std::ffi::OsStr::new("--no-default-browser-check"),
+            // Anti-bot flags
+            std::ffi::OsStr::new("--disable-blink-features=AutomationControlled"),
+            std::ffi::OsStr::new("--disable-infobars"),
+            std::ffi::OsStr::new("--restore-last-session"),
+            std::ffi::OsStr::new("--password-store=basic"),
+        ];
+        let (proxy_arg, proxy_auth) = match proxy {
+            Some((server, auth)) => {
+                eprintln!("[Hands] Using proxy: {}", server);
+                (Some(format!("--proxy-server={}", server)), auth)
+            }
+            None => (None, None),
+        };
+        if let Some(arg) = &proxy_arg {
+            args.push(std::ffi::OsStr::new(arg));
+        }
+
         let options = LaunchOptions {
             headless: false,
             path: Some(chrome_path),
             user_data_dir: Some(agent_profile.clone()),
             // port: Some(9222), // Let headless_chrome pick a random port to avoid conflicts
-            args: vec![
-                std::ffi::OsStr::new("--no-first-run"),
-                std::ffi::OsStr::new("--no-default-browser-check"),
-                // Anti-bot flags
-                std::ffi::OsStr::new("--disable-blink-features=AutomationControlled"),
-                std::ffi::OsStr::new("--disable-infobars"),
-                std::ffi::OsStr::new("--restore-last-session"),
-                std::ffi::OsStr::new("--password-store=basic"),
-            ],
-            idle_browser_timeout: std::time::Duration::from_secs(60),
+            args,
+            idle_browser_timeout: idle_timeout,
             ..Default::default()
         };
 
@@ -87,20 +222,454 @@ impl BrowserSession {
 
         eprintln!("[Hands] Chrome started, creating tab...");
         let tab = browser.new_tab()?;
+        let setup = TabSetup {
+            downloads_dir: downloads.dir.clone(),
+            proxy_auth,
+            stealth,
+            user_agent,
+            capture_console,
+        };
+        setup.apply(&tab);
+        grant_configured_permissions(&tab);
         tab.navigate_to("about:blank")?;
 
         eprintln!("[Hands] Chrome ready.");
 
         Ok(Self {
-            _browser: browser,
+            browser: Some(browser),
+            home_tab: tab.clone(),
             tab,
+            downloads,
+            setup,
+            history: Vec::new(),
         })
     }
     pub fn new_tab(&mut self) -> Result<()> {
-        let tab = self._browser.new_tab()?;
+        let Some(browser) = &self.browser else {
+            anyhow::bail!("The browser was closed; relaunch it");
+        };
+        let tab = browser.new_tab()?;
+        self.setup.apply(&tab);
+        self.home_tab = tab.clone();
         self.tab = tab;
         Ok(())
     }
+
+    /// Number of tabs the browser currently knows about.
+    pub fn tab_count(&self) -> usize {
+        self.browser
+            .as_ref()
+            .map_or(0, |browser| browser.get_tabs().lock().unwrap().len())
+    }
+
+    /// Shut the browser down, releasing its profile lock so another can
+    /// launch. Every later step on this session fails.
+    pub fn close(&mut self) {
+        self.browser = None;
+    }
+
+    /// If the page opened a tab (popup, target=_blank) since `tabs_before`
+    /// was taken, point at the newest one. Returns true if we switched.
+    pub fn follow_new_tab(&mut self, tabs_before: usize) -> bool {
+        let Some(browser) = &self.browser else {
+            return false;
+        };
+        let tabs = browser.get_tabs().lock().unwrap();
+        if tabs.len() <= tabs_before {
+            return false;
+        }
+        match tabs.last() {
+            Some(newest) if !Arc::ptr_eq(newest, &self.tab) => {
+                self.setup.apply(newest);
+                self.tab = newest.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Go back to the tab the current task started in.
+    pub fn switch_to_home_tab(&mut self) {
+        self.tab = self.home_tab.clone();
+    }
+
+    /// Forget the pages visited by the previous task.
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Remember a visited page. Revisits move it to the end; only the most
+    /// recent `MAX_HISTORY` pages are kept.
+    pub fn record_visit(&mut self, title: &str, url: &str) {
+        const MAX_HISTORY: usize = 10;
+        if url.is_empty() || url == "about:blank" {
+            return;
+        }
+        self.history.retain(|(_, u)| u != url);
+        self.history.push((title.to_string(), url.to_string()));
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+    }
+
+    /// The visited pages, oldest first.
+    pub fn history(&self) -> &[(String, String)] {
+        &self.history
+    }
+
+    /// URL of the most recent visited page whose title contains `needle`
+    /// (case-insensitive).
+    pub fn find_in_history(&self, needle: &str) -> Option<String> {
+        let needle = needle.to_lowercase();
+        self.history
+            .iter()
+            .rev()
+            .find(|(title, _)| title.to_lowercase().contains(&needle))
+            .map(|(_, url)| url.clone())
+    }
+}
+
+/// The agent's download directory, plus which files have already been reported.
+pub struct Downloads {
+    pub dir: PathBuf,
+    seen: Mutex<HashSet<PathBuf>>,
+}
+
+impl Downloads {
+    fn new(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        // Anything already on disk belongs to an earlier run
+        let seen = list_finished_files(&dir)?.into_iter().collect();
+        Ok(Self {
+            dir,
+            seen: Mutex::new(seen),
+        })
+    }
+
+    /// Block until a finished download we haven't reported yet shows up.
+    pub fn wait_for_new(&self, timeout: Duration) -> Result<PathBuf> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            {
+                let mut seen = self.seen.lock().unwrap();
+                let newest = list_finished_files(&self.dir)?
+                    .into_iter()
+                    .filter(|p| !seen.contains(p))
+                    .max_by_key(|p| p.metadata().and_then(|m| m.modified()).ok());
+                if let Some(path) = newest {
+                    seen.insert(path.clone());
+                    return Ok(path);
+                }
+            }
+
+            if Instant::now() >= deadline {
+                anyhow::bail!("No download finished within {}ms", timeout.as_millis());
+            }
+            std::thread::sleep(Duration::from_millis(250));
+        }
+    }
+}
+
+/// Read a downloaded file as text, refusing anything over `max_bytes` or
+/// that doesn't look like text.
+pub fn read_download_text(path: &Path, max_bytes: u64) -> Result<String> {
+    let size = path.metadata()?.len();
+    if size > max_bytes {
+        anyhow::bail!(
+            "{} is {} bytes, over the {} byte limit (DOWNLOAD_MAX_BYTES)",
+            path.display(),
+            size,
+            max_bytes
+        );
+    }
+    let bytes = std::fs::read(path)?;
+    if bytes.contains(&0) {
+        anyhow::bail!("{} looks like a binary file", path.display());
+    }
+    String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("{} is not UTF-8 text", path.display()))
+}
+
+/// Files in `dir`, skipping Chrome's in-progress `.crdownload` partials.
+fn list_finished_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let partial = path.extension().is_some_and(|ext| ext == "crdownload");
+        if path.is_file() && !partial {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Route downloads from this tab into the agent's download directory.
+fn allow_downloads(tab: &Tab, dir: &Path) {
+    let result = tab.call_method(Page::SetDownloadBehavior {
+        behavior: Page::SetDownloadBehaviorBehaviorOption::Allow,
+        download_path: Some(dir.to_string_lossy().into_owned()),
+    });
+    if let Err(e) = result {
+        eprintln!("[Hands] Warning: Could not set download directory: {}", e);
+    }
+}
+
+/// Runs before any page script when STEALTH=1. Masks the usual signs of an
+/// automated browser; sites that only check these then treat us as a user.
+const STEALTH_JS: &str = r#"
+Object.defineProperty(Navigator.prototype, 'webdriver', { get: () => undefined });
+if (navigator.plugins.length === 0) {
+  Object.defineProperty(Navigator.prototype, 'plugins', {
+    get: () => [1, 2, 3].map(i => ({ name: 'Plugin ' + i, filename: 'plugin' + i, length: 0 })),
+  });
+}
+if (!navigator.languages || navigator.languages.length === 0) {
+  Object.defineProperty(Navigator.prototype, 'languages', { get: () => ['en-US', 'en'] });
+}
+if (!window.chrome) {
+  window.chrome = { runtime: {}, app: { isInstalled: false }, csi: () => ({}), loadTimes: () => ({}) };
+}
+"#;
+
+fn apply_stealth(tab: &Tab) {
+    let result = tab.call_method(Page::AddScriptToEvaluateOnNewDocument {
+        source: STEALTH_JS.to_string(),
+        world_name: None,
+        include_command_line_api: None,
+        run_immediately: None,
+    });
+    if let Err(e) = result {
+        eprintln!("[Hands] Warning: Could not enable stealth mode: {}", e);
+    }
+}
+
+/// Last main-frame response (URL, HTTP status) per tab, keyed by target id.
+static PAGE_STATUS: LazyLock<Mutex<HashMap<String, (String, u16)>>> =
+    LazyLock::new(Default::default);
+
+/// Record the HTTP status of every main-frame document `tab` loads.
+fn track_status(tab: &Tab) {
+    use headless_chrome::protocol::cdp::Network::ResourceType;
+
+    // A page target's main frame shares its id
+    let target_id = tab.get_target_id().clone();
+    let handler: headless_chrome::browser::tab::ResponseHandler =
+        Box::new(move |params, _fetch_body| {
+            if params.Type == ResourceType::Document
+                && params.frame_id.as_deref() == Some(target_id.as_str())
+            {
+                PAGE_STATUS.lock().unwrap().insert(
+                    target_id.clone(),
+                    (params.response.url, params.response.status as u16),
+                );
+            }
+        });
+    if let Err(e) = tab.register_response_handling("agent-page-status", handler) {
+        eprintln!("[Hands] Warning: Could not track HTTP status: {}", e);
+    }
+}
+
+/// HTTP status of the document loaded in `tab`, if its response was seen.
+/// Pages restored from cache fire no response, so a stale entry for a
+/// different URL is ignored.
+pub fn page_status(tab: &Tab) -> Option<u16> {
+    let url = tab.get_url();
+    let statuses = PAGE_STATUS.lock().unwrap();
+    let (seen_url, status) = statuses.get(tab.get_target_id())?;
+    (seen_url.split('#').next() == url.split('#').next()).then_some(*status)
+}
+
+/// A base64 JPEG of `tab`'s viewport, ready for an image message.
+pub fn screenshot_base64(tab: &Tab) -> Result<String> {
+    let shot = tab.call_method(Page::CaptureScreenshot {
+        format: Some(Page::CaptureScreenshotFormatOption::Jpeg),
+        quality: Some(70),
+        clip: None,
+        from_surface: Some(true),
+        capture_beyond_viewport: None,
+        optimize_for_speed: None,
+    })?;
+    Ok(shot.data)
+}
+
+/// Console entries kept per tab; older ones are dropped.
+const CONSOLE_LOG_MAX: usize = 10;
+
+/// Console errors and warnings per tab since its last navigation, keyed by
+/// target id, oldest first.
+static CONSOLE_LOG: LazyLock<Mutex<HashMap<String, VecDeque<String>>>> =
+    LazyLock::new(Default::default);
+
+/// Record console errors/warnings, uncaught exceptions and browser log
+/// errors on `tab`, starting afresh on each main-frame navigation.
+fn track_console(tab: &Tab) {
+    use headless_chrome::protocol::cdp::Log::LogEntryLevel;
+    use headless_chrome::protocol::cdp::Runtime::ConsoleAPICalledEventTypeOption as ConsoleType;
+    use headless_chrome::protocol::cdp::types::Event;
+
+    let target_id = tab.get_target_id().clone();
+    let listener = Arc::new(move |event: &Event| {
+        let entry = match event {
+            Event::RuntimeConsoleAPICalled(e) => {
+                let level = match e.params.Type {
+                    ConsoleType::Error | ConsoleType::Assert => "error",
+                    ConsoleType::Warning => "warning",
+                    _ => return,
+                };
+                let text: Vec<String> = e
+                    .params
+                    .args
+                    .iter()
+                    .map(|arg| match &arg.value {
+                        Some(serde_json::Value::String(s)) => s.clone(),
+                        Some(v) => v.to_string(),
+                        None => arg.description.clone().unwrap_or_default(),
+                    })
+                    .collect();
+                format!("console.{}: {}", level, text.join(" "))
+            }
+            Event::RuntimeExceptionThrown(e) => {
+                let details = &e.params.exception_details;
+                let text = details
+                    .exception
+                    .as_ref()
+                    .and_then(|ex| ex.description.clone())
+                    .unwrap_or_else(|| details.text.clone());
+                format!("uncaught: {}", text)
+            }
+            Event::LogEntryAdded(e) => {
+                let level = match e.params.entry.level {
+                    LogEntryLevel::Error => "error",
+                    LogEntryLevel::Warning => "warning",
+                    _ => return,
+                };
+                format!("{}: {}", level, e.params.entry.text)
+            }
+            Event::PageFrameNavigated(e) if e.params.frame.parent_id.is_none() => {
+                CONSOLE_LOG.lock().unwrap().remove(&target_id);
+                return;
+            }
+            _ => return,
+        };
+
+        let mut logs = CONSOLE_LOG.lock().unwrap();
+        let log = logs.entry(target_id.clone()).or_default();
+        if log.len() == CONSOLE_LOG_MAX {
+            log.pop_front();
+        }
+        log.push_back(entry.chars().take(200).collect());
+    });
+
+    let result = tab
+        .enable_runtime()
+        .and_then(|tab| tab.enable_log())
+        .and_then(|tab| tab.add_event_listener(listener));
+    if let Err(e) = result {
+        eprintln!("[Hands] Warning: Could not capture console: {}", e);
+    }
+}
+
+/// Console errors and warnings logged by the page in `tab`, oldest first.
+pub fn console_errors(tab: &Tab) -> Vec<String> {
+    CONSOLE_LOG
+        .lock()
+        .unwrap()
+        .get(tab.get_target_id())
+        .map(|log| log.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Split BROWSER_PROXY (`[scheme://][user:pass@]host:port`) into the
+/// `--proxy-server` value and optional credentials.
+fn parse_proxy(value: &str) -> (String, Option<(String, String)>) {
+    let (scheme, rest) = match value.split_once("://") {
+        Some((scheme, rest)) => (format!("{}://", scheme), rest),
+        None => (String::new(), value),
+    };
+    match rest.rsplit_once('@') {
+        Some((userinfo, host)) => {
+            let (user, pass) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+            (
+                format!("{}{}", scheme, host),
+                Some((user.to_string(), pass.to_string())),
+            )
+        }
+        None => (value.to_string(), None),
+    }
+}
+
+/// Answer proxy auth challenges on `tab`. Chrome ignores credentials in
+/// --proxy-server, so they go through the Fetch domain instead.
+fn authenticate_proxy(tab: &Tab, auth: Option<&(String, String)>) {
+    let Some((user, pass)) = auth else {
+        return;
+    };
+    let result = tab
+        .authenticate(Some(user.clone()), Some(pass.clone()))
+        .and_then(|tab| tab.enable_fetch(None, Some(true)));
+    if let Err(e) = result {
+        eprintln!("[Hands] Warning: Could not set up proxy auth: {}", e);
+    }
+}
+
+/// Permissions that GRANT_PERMISSIONS and the GrantPermission step may
+/// grant. Everything else keeps Chrome's default, which is to deny.
+pub const SUPPORTED_PERMISSIONS: &[&str] = &[
+    "geolocation",
+    "notifications",
+    "audioCapture",
+    "videoCapture",
+    "clipboardReadWrite",
+    "clipboardSanitizedWrite",
+    "midi",
+];
+
+fn permission_type(name: &str) -> Result<headless_chrome::protocol::cdp::Browser::PermissionType> {
+    let name = SUPPORTED_PERMISSIONS
+        .iter()
+        .find(|p| p.eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unsupported permission '{}' (supported: {})",
+                name,
+                SUPPORTED_PERMISSIONS.join(", ")
+            )
+        })?;
+    Ok(serde_json::from_value(serde_json::json!(name))?)
+}
+
+/// Grant `names` to `origin`, or to every origin when it is `None`.
+pub fn grant_permissions(tab: &Tab, names: &[&str], origin: Option<String>) -> Result<()> {
+    use headless_chrome::protocol::cdp::Browser::GrantPermissions;
+
+    let permissions = names
+        .iter()
+        .map(|name| permission_type(name))
+        .collect::<Result<Vec<_>>>()?;
+    tab.call_method(GrantPermissions {
+        permissions,
+        origin,
+        browser_context_id: None,
+    })?;
+    Ok(())
+}
+
+/// Grant the comma-separated GRANT_PERMISSIONS list to every origin.
+fn grant_configured_permissions(tab: &Tab) {
+    let Ok(value) = std::env::var("GRANT_PERMISSIONS") else {
+        return;
+    };
+    let names: Vec<&str> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .collect();
+    if names.is_empty() {
+        return;
+    }
+    match grant_permissions(tab, &names, None) {
+        Ok(()) => eprintln!("[Hands] Granted permissions: {}", names.join(", ")),
+        Err(e) => eprintln!("[Hands] Warning: Could not grant permissions: {}", e),
+    }
 }
 
 fn sync_profile(agent_profile: &std::path::Path) -> Result<()> {
@@ -196,3 +765,52 @@ fn kill_chrome_processes() {
         .stderr(std::process::Stdio::null())
         .status();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_proxy_without_credentials() {
+        assert_eq!(
+            parse_proxy("http://proxy.local:8080"),
+            ("http://proxy.local:8080".to_string(), None)
+        );
+        assert_eq!(parse_proxy("proxy.local:3128").0, "proxy.local:3128");
+    }
+
+    #[test]
+    fn maps_supported_permissions() {
+        for name in SUPPORTED_PERMISSIONS {
+            assert!(permission_type(name).is_ok(), "{}", name);
+        }
+        assert!(permission_type("Geolocation").is_ok());
+        assert!(permission_type("storageAccess").is_err());
+    }
+
+    #[test]
+    fn reads_small_text_downloads_only() {
+        let dir = std::env::temp_dir().join(format!("agent-dl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("export.csv");
+        std::fs::write(&csv, "a,b\n1,2\n").unwrap();
+        let bin = dir.join("image.png");
+        std::fs::write(&bin, [0x89, b'P', b'N', b'G', 0, 0]).unwrap();
+
+        assert_eq!(read_download_text(&csv, 100).unwrap(), "a,b\n1,2\n");
+        assert!(read_download_text(&csv, 4).is_err());
+        assert!(read_download_text(&bin, 100).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_proxy_credentials() {
+        assert_eq!(
+            parse_proxy("socks5://me:p@ss@proxy.local:1080"),
+            (
+                "socks5://proxy.local:1080".to_string(),
+                Some(("me".to_string(), "p@ss".to_string()))
+            )
+        );
+    }
+}
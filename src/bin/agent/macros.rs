@@ -0,0 +1,70 @@
+use anyhow::{Context, Result, anyhow};
+use std::path::PathBuf;
+
+use crate::types::Step;
+
+/// Saved macros live here, one JSON array of steps per file.
+const MACROS_DIR: &str = "macros";
+
+/// Turn a task description into a file-name-safe macro name,
+/// e.g. "Search Rust docs!" -> "search-rust-docs".
+pub fn name_for_task(task: &str) -> String {
+    let mut name = String::new();
+    for c in task.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+        if name.len() >= 48 {
+            break;
+        }
+    }
+    name.trim_end_matches('-').to_string()
+}
+
+fn macro_path(name: &str) -> Result<PathBuf> {
+    // Names come from the web UI, so keep them inside MACROS_DIR
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!("Invalid macro name '{}'", name));
+    }
+    Ok(PathBuf::from(MACROS_DIR).join(format!("{}.json", name)))
+}
+
+/// Write `steps` to macros/<name>.json, replacing any previous recording.
+pub fn save(name: &str, steps: &[Step]) -> Result<PathBuf> {
+    let path = macro_path(name)?;
+    std::fs::create_dir_all(MACROS_DIR)?;
+    std::fs::write(&path, serde_json::to_string_pretty(steps)?)?;
+    Ok(path)
+}
+
+/// Read the steps recorded in macros/<name>.json.
+pub fn load(name: &str) -> Result<Vec<Step>> {
+    let path = macro_path(name)?;
+    let data =
+        std::fs::read_to_string(&path).with_context(|| format!("No macro named '{}'", name))?;
+    serde_json::from_str(&data).with_context(|| format!("Macro '{}' is not valid", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_slugs() {
+        assert_eq!(name_for_task("Search Rust docs!"), "search-rust-docs");
+        assert_eq!(name_for_task("  a  /  b "), "a-b");
+    }
+
+    #[test]
+    fn rejects_path_like_names() {
+        assert!(macro_path("../memory").is_err());
+        assert!(macro_path("").is_err());
+        assert!(macro_path("search-rust-docs").is_ok());
+    }
+}
@@ -2,80 +2,312 @@ mod brain;
 mod dom;
 mod face;
 mod hands;
+mod macros;
 mod types;
 
 use anyhow::Result;
 use dotenvy::dotenv;
-use face::AgentEvent;
-use tokio::sync::broadcast;
-use types::{MAX_STEPS_PER_TASK, Step};
+use face::{AgentCommand, AgentEvent};
+use std::sync::atomic::Ordering;
+use types::{MAX_STEPS_PER_TASK, Step, TaskOutcome};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
 
     eprintln!("[Agent] Starting AI Browser Agent...");
+    let session_id = format!("{:016x}", rand::random::<u64>());
+    eprintln!("[Agent] Session id: {}", session_id);
+
+    // One-shot mode: `agent -- "some task"` runs it without the web UI
+    let task_args: Vec<String> = std::env::args().skip(1).collect();
+    if !task_args.is_empty() {
+        let succeeded = run_headless(&task_args.join(" "), session_id).await?;
+        std::process::exit(if succeeded { 0 } else { 1 });
+    }
 
     // 1. Launch web UI first (so user sees something immediately)
-    let (mut cmd_rx, event_tx) = face::start_server().await;
+    let (mut cmd_rx, ui) = face::start_server(session_id).await;
 
-    // 2. Launch browser in a blocking task (it can take a while)
-    eprintln!("[Agent] Launching Chrome...");
-    let mut session = tokio::task::spawn_blocking(|| hands::BrowserSession::launch())
-        .await
-        .map_err(|e| anyhow::anyhow!("Browser launch panicked: {}", e))??;
-    eprintln!("[Agent] Chrome launched successfully.");
+    // 2. Launch browser (it can take a while). On failure the UI stays up
+    //    so the user can see why and retry via /relaunch.
+    let mut session = launch_browser(&ui).await;
 
     let mut brain = brain::Brain::new()?;
-    eprintln!("[Agent] Brain ready. Waiting for commands...");
+    eprintln!("[Agent] Brain ready.");
+
+    // 3. Optionally pick up where a crashed run left off
+    if std::env::var("RESUME").is_ok_and(|v| v == "1")
+        && let Some(session) = session.as_mut()
+    {
+        ui.busy.store(true, Ordering::Relaxed);
+        resume_task(session, &mut brain, &ui).await;
+        ui.busy.store(false, Ordering::Relaxed);
+    }
 
-    // 3. Wait for commands from the web UI
-    while let Some(user_command) = cmd_rx.recv().await {
-        eprintln!("[Agent] Received command: '{}'", user_command);
-        run_task(&mut session, &mut brain, &user_command, &event_tx).await;
+    // 4. Wait for commands from the web UI
+    eprintln!("[Agent] Waiting for commands...");
+    while let Some(command) = cmd_rx.recv().await {
+        ui.busy.store(true, Ordering::Relaxed);
+        match command {
+            AgentCommand::Run(user_command) => {
+                eprintln!("[Agent] Received command: '{}'", user_command);
+                match session.as_mut() {
+                    Some(session) => {
+                        run_task(session, &mut brain, &user_command, &ui).await;
+                    }
+                    None => report_browser_unavailable(&ui),
+                }
+            }
+            AgentCommand::Resume => {
+                eprintln!("[Agent] Received resume request");
+                match session.as_mut() {
+                    Some(session) => {
+                        resume_task(session, &mut brain, &ui).await;
+                    }
+                    None => report_browser_unavailable(&ui),
+                }
+            }
+            AgentCommand::Replay(name) => {
+                eprintln!("[Agent] Received replay request: '{}'", name);
+                match session.as_mut() {
+                    Some(session) => {
+                        replay_macro(session, &name, &ui).await;
+                    }
+                    None => report_browser_unavailable(&ui),
+                }
+            }
+            AgentCommand::Relaunch => {
+                eprintln!("[Agent] Received relaunch request");
+                // Release the old browser (and its profile lock) first
+                drop(session.take());
+                session = launch_browser(&ui).await;
+            }
+        }
+        ui.busy.store(false, Ordering::Relaxed);
     }
 
     Ok(())
 }
 
+/// Run a single task without the web server and print the outcome as JSON
+/// on stdout. Returns whether the task succeeded.
+async fn run_headless(command: &str, session_id: String) -> Result<bool> {
+    if std::env::var("MANUAL_STEP").is_ok_and(|v| v == "1") {
+        anyhow::bail!("MANUAL_STEP needs the web UI to approve steps; unset it for one-shot runs");
+    }
+    let (ui, _cmd_rx) = face::AppState::new(session_id);
+
+    let Some(mut session) = launch_browser(&ui).await else {
+        let message = ui.browser_error.lock().unwrap().clone().unwrap_or_default();
+        println!(
+            "{}",
+            serde_json::json!({
+                "success": false,
+                "error": format!("Browser unavailable: {}", message),
+                "session_id": ui.session_id,
+            })
+        );
+        return Ok(false);
+    };
+    let mut brain = brain::Brain::new()?;
+
+    let outcome = run_task(&mut session, &mut brain, command, &ui).await;
+    let (succeeded, report) = match outcome {
+        TaskOutcome::Complete { summary } => (
+            true,
+            serde_json::json!({"success": true, "summary": summary, "session_id": ui.session_id}),
+        ),
+        TaskOutcome::Failed { message } => (
+            false,
+            serde_json::json!({"success": false, "error": message, "session_id": ui.session_id}),
+        ),
+    };
+    println!("{}", report);
+
+    Ok(succeeded)
+}
+
+/// Launch Chrome in a blocking task, reporting failure to the UI instead of exiting.
+async fn launch_browser(ui: &face::AppState) -> Option<hands::BrowserSession> {
+    eprintln!("[Agent] Launching Chrome...");
+    let result = tokio::task::spawn_blocking(hands::BrowserSession::launch)
+        .await
+        .map_err(|e| anyhow::anyhow!("Browser launch panicked: {}", e))
+        .and_then(|launched| launched);
+
+    match result {
+        Ok(session) => {
+            eprintln!("[Agent] Chrome launched successfully.");
+            *ui.browser_error.lock().unwrap() = None;
+            let _ = ui.event_tx.send(AgentEvent::Ready);
+            Some(session)
+        }
+        Err(e) => {
+            eprintln!("[Agent] Browser unavailable: {:#}", e);
+            *ui.browser_error.lock().unwrap() = Some(format!("{:#}", e));
+            report_browser_unavailable(ui);
+            None
+        }
+    }
+}
+
+fn report_browser_unavailable(ui: &face::AppState) {
+    let message = ui
+        .browser_error
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| "unknown error".to_string());
+    let _ = ui.event_tx.send(AgentEvent::BrowserUnavailable { message });
+}
+
 async fn run_task(
     session: &mut hands::BrowserSession,
     brain: &mut brain::Brain,
     command: &str,
-    events: &broadcast::Sender<AgentEvent>,
-) {
+    ui: &face::AppState,
+) -> TaskOutcome {
     brain.start_task(command);
+    ui.stats.tasks.fetch_add(1, Ordering::Relaxed);
+
+    session.clear_history();
 
     // Always start a new task in a new tab
     if let Err(e) = session.new_tab() {
         eprintln!("[Agent] Warning: Failed to open new tab for task: {}", e);
     }
 
+    // Save a step when every task begins on the same site
+    if let Some(start_url) = std::env::var("START_URL").ok().filter(|u| !u.is_empty()) {
+        eprintln!("[Agent] Opening start URL: {}", start_url);
+        let tab = session.tab.clone();
+        let downloads = session.downloads.clone();
+        let step = Step::Navigate { url: start_url };
+        let page_state = tokio::task::spawn_blocking(move || {
+            let error =
+                execute_step_on_tab(&tab, &downloads, &step, &mut Vec::new(), &mut Vec::new())
+                    .err()
+                    .map(|e| format!("{:#}", e));
+            capture_page_state(&tab, Vec::new(), error)
+        })
+        .await
+        .unwrap();
+        brain.observe(&page_state);
+        vision_fallback(session, brain, &page_state).await;
+    }
+
+    drive_task(session, brain, ui, Some(&macros::name_for_task(command))).await
+}
+
+/// Continue an unfinished task from memory.json, starting from the current page.
+async fn resume_task(
+    session: &mut hands::BrowserSession,
+    brain: &mut brain::Brain,
+    ui: &face::AppState,
+) -> TaskOutcome {
+    let events = &ui.event_tx;
+    if !brain.has_unfinished_task() {
+        eprintln!("[Agent] Nothing to resume.");
+        let message = "No unfinished task in memory to resume".to_string();
+        let _ = events.send(AgentEvent::TaskError {
+            message: message.clone(),
+            session_id: ui.session_id.clone(),
+        });
+        let _ = events.send(AgentEvent::Ready);
+        return TaskOutcome::Failed { message };
+    }
+
+    eprintln!("[Agent] Resuming unfinished task from memory...");
+    ui.stats.tasks.fetch_add(1, Ordering::Relaxed);
+
+    // The browser may have restarted, so show the Brain where it is now
+    let tab = session.tab.clone();
+    let page_state =
+        tokio::task::spawn_blocking(move || capture_page_state(&tab, Vec::new(), None))
+            .await
+            .unwrap();
+    brain.observe(&page_state);
+    vision_fallback(session, brain, &page_state).await;
+
+    drive_task(session, brain, ui, None).await
+}
+
+/// The observe-think-act loop shared by new and resumed tasks.
+///
+/// With a `macro_name`, the successful steps of a completed task are saved
+/// so /replay can run them again.
+async fn drive_task(
+    session: &mut hands::BrowserSession,
+    brain: &mut brain::Brain,
+    ui: &face::AppState,
+    macro_name: Option<&str>,
+) -> TaskOutcome {
+    let events = &ui.event_tx;
+    let manual_step = std::env::var("MANUAL_STEP").is_ok_and(|v| v == "1");
+    let recover_after: usize = std::env::var("RECOVER_AFTER_ERRORS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3);
+    let max_task_secs: Option<u64> = std::env::var("MAX_TASK_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&secs| secs > 0);
+    let started = std::time::Instant::now();
     let mut step_count = 0;
+    let mut consecutive_errors = 0;
+    // Steps that ran without error, saved as a macro if the task completes
+    let mut recorded = Vec::new();
 
-    loop {
+    let outcome = loop {
         if step_count >= MAX_STEPS_PER_TASK {
             eprintln!("[Agent] Step limit reached");
+            let message = format!("Reached maximum step limit ({})", MAX_STEPS_PER_TASK);
             let _ = events.send(AgentEvent::TaskError {
-                message: format!("Reached maximum step limit ({})", MAX_STEPS_PER_TASK),
+                message: message.clone(),
+                session_id: ui.session_id.clone(),
             });
-            break;
+            break TaskOutcome::Failed { message };
+        }
+
+        if let Some(limit) = max_task_secs
+            && started.elapsed().as_secs() >= limit
+        {
+            eprintln!("[Agent] Time limit reached");
+            let message = format!(
+                "Task exceeded time limit ({}s elapsed, MAX_TASK_SECONDS={})",
+                started.elapsed().as_secs(),
+                limit
+            );
+            let _ = events.send(AgentEvent::TaskError {
+                message: message.clone(),
+                session_id: ui.session_id.clone(),
+            });
+            break TaskOutcome::Failed { message };
         }
 
         eprintln!("[Agent] Asking Brain (LLM) for next step...");
         let _ = events.send(AgentEvent::Thinking);
 
         let step_result = brain.decide_next_step().await;
-        eprintln!("[Agent] Brain replied. Result: {:?}", step_result);
+        ui.stats
+            .tokens
+            .store(brain.tokens_used(), Ordering::Relaxed);
+        eprintln!(
+            "[Agent] Brain replied. Result: {:?}",
+            step_result.as_ref().map(|d| d.step.redacted())
+        );
 
-        let step = match step_result {
-            Ok(s) => s,
+        let types::Decision { step, reason } = match step_result {
+            Ok(d) => d,
             Err(e) => {
                 eprintln!("[Agent] LLM error: {:#}", e);
+                let message = format!("{:#}", e);
                 let _ = events.send(AgentEvent::TaskError {
-                    message: format!("{:#}", e),
+                    message: message.clone(),
+                    session_id: ui.session_id.clone(),
                 });
-                break;
+                break TaskOutcome::Failed { message };
             }
         };
 
@@ -85,76 +317,684 @@ async fn run_task(
             eprintln!("[Agent] Task complete: {}", summary);
             let _ = events.send(AgentEvent::TaskComplete {
                 summary: summary.clone(),
+                session_id: ui.session_id.clone(),
+                duration_ms: started.elapsed().as_millis() as u64,
+                steps: step_count - 1,
             });
-            break;
+            break TaskOutcome::Complete {
+                summary: summary.clone(),
+            };
         }
 
-        // Handle NewTab specially (requires session, not just tab)
-        if let Step::NewTab = step {
-            eprintln!("[Agent] Opening new tab...");
-            if let Err(e) = session.new_tab() {
-                eprintln!("[Agent] Failed to open new tab: {}", e);
+        // Keep passwords and REDACT_VALUES out of logs, events, macros and memory
+        let sensitive = step.secret().is_some()
+            && (brain.is_secret(&step) || targets_password_field(session, &step).await);
+        if sensitive && let Some(secret) = step.secret() {
+            brain.add_secret(secret);
+        }
+        let shown = if sensitive {
+            step.redacted()
+        } else {
+            step.clone()
+        };
+
+        // Human-in-the-loop: hold the step until the UI approves or rejects it
+        if manual_step {
+            let _ = events.send(AgentEvent::StepProposed {
+                number: step_count,
+                kind: step.kind(),
+                description: format!("{:?}", shown),
+                reason: reason.clone(),
+            });
+            eprintln!("[Agent] Waiting for approval of step {}...", step_count);
+            if !ui.approvals.wait().await {
+                eprintln!("[Agent] Step {} rejected", step_count);
+                brain.step_rejected();
+                continue;
             }
         }
 
-        let description = format!("{:?}", step);
+        let description = format!("{:?}", shown);
         eprintln!("[Agent] Step {}: {}", step_count, description);
+        ui.stats.steps.fetch_add(1, Ordering::Relaxed);
         let _ = events.send(AgentEvent::Step {
             number: step_count,
+            limit: MAX_STEPS_PER_TASK,
+            kind: step.kind(),
             description,
+            reason,
         });
 
-        // Execute in a blocking context so we don't stall tokio
-        let tab = session.tab.clone();
-        let step_clone = step.clone();
-        let page_state = tokio::task::spawn_blocking(move || {
-            let mut extracted = Vec::new();
-            let mut error = None;
-
-            match execute_step_on_tab(&tab, &step_clone, &mut extracted) {
-                Ok(()) => {}
-                Err(e) => error = Some(format!("{:#}", e)),
-            }
-
-            let url = crate::dom::get_current_url(&tab).unwrap_or_else(|_| "unknown".into());
-            let title = crate::dom::get_page_title(&tab).unwrap_or_else(|_| "untitled".into());
-            let dom_snapshot =
-                crate::dom::capture_dom_snapshot(&tab).unwrap_or_else(|_| String::new());
+        if let Step::ClearExtractions = step {
+            brain.clear_extractions();
+        }
+        let page_state = perform_step(session, &step, events).await;
+        if let Err(e) = append_results(&page_state.extracted, &ui.session_id) {
+            eprintln!("[Agent] Warning: Failed to save extractions: {:#}", e);
+        }
+        if page_state.error.is_none() {
+            recorded.push(shown);
+        }
 
-            types::PageState {
-                url,
-                title,
-                dom_snapshot,
-                extracted,
-                error,
-            }
-        })
-        .await
-        .unwrap();
+        // A failed assertion is the answer to a verification task, not
+        // something to retry around
+        if let (Step::Assert { .. }, Some(err)) = (&step, &page_state.error) {
+            eprintln!("[Agent] {}", err);
+            let message = err.clone();
+            let _ = events.send(AgentEvent::TaskError {
+                message: message.clone(),
+                session_id: ui.session_id.clone(),
+            });
+            break TaskOutcome::Failed { message };
+        }
 
         if let Some(ref err) = page_state.error {
             eprintln!("[Agent] Step error: {}", err);
             let _ = events.send(AgentEvent::StepError {
                 message: err.clone(),
             });
+            consecutive_errors += 1;
+        } else {
+            consecutive_errors = 0;
         }
 
         brain.observe(&page_state);
+        vision_fallback(session, brain, &page_state).await;
+
+        // A wedged browser fails every step; reset it rather than burn the
+        // rest of the step budget
+        if recover_after > 0 && consecutive_errors >= recover_after {
+            let action = match recover_browser(session).await {
+                Ok(action) => action,
+                Err(e) => {
+                    eprintln!("[Agent] Browser recovery failed: {:#}", e);
+                    let message = format!(
+                        "{} steps failed in a row and the browser could not be recovered: {:#}. \
+                         Relaunch it from the UI before the next task.",
+                        consecutive_errors, e
+                    );
+                    let _ = events.send(AgentEvent::TaskError {
+                        message: message.clone(),
+                        session_id: ui.session_id.clone(),
+                    });
+                    break TaskOutcome::Failed { message };
+                }
+            };
+            let message = format!(
+                "{} steps failed in a row, so {}",
+                consecutive_errors, action
+            );
+            eprintln!("[Agent] {}", message);
+            let _ = events.send(AgentEvent::Recovered {
+                message: message.clone(),
+            });
+            consecutive_errors = 0;
+
+            let tab = session.tab.clone();
+            let mut page_state =
+                tokio::task::spawn_blocking(move || capture_page_state(&tab, Vec::new(), None))
+                    .await
+                    .unwrap();
+            page_state.notes.push(format!(
+                "{}. Navigate back to where you were to continue.",
+                message
+            ));
+            brain.observe(&page_state);
+        }
+    };
+
+    if let (TaskOutcome::Complete { .. }, Some(name)) = (&outcome, macro_name)
+        && !recorded.is_empty()
+    {
+        match macros::save(name, &recorded) {
+            Ok(path) => eprintln!("[Agent] Saved macro '{}' to {:?}", name, path),
+            Err(e) => eprintln!("[Agent] Warning: Failed to save macro: {:#}", e),
+        }
     }
 
     let _ = events.send(AgentEvent::Ready);
+    outcome
+}
+
+/// Snapshots shorter than this are treated as blind for VISION_FALLBACK.
+const VISION_MIN_SNAPSHOT_CHARS: usize = 50;
+
+/// With VISION_FALLBACK=1, attach a screenshot to the next LLM request when
+/// the DOM snapshot is (nearly) empty, e.g. on canvas-only pages. The model
+/// must accept images.
+async fn vision_fallback(
+    session: &hands::BrowserSession,
+    brain: &mut brain::Brain,
+    page_state: &types::PageState,
+) {
+    static ENABLED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    let enabled = *ENABLED.get_or_init(|| std::env::var("VISION_FALLBACK").is_ok_and(|v| v == "1"));
+    if !enabled
+        || page_state.url.starts_with("about:")
+        || page_state.dom_snapshot.trim().chars().count() >= VISION_MIN_SNAPSHOT_CHARS
+    {
+        return;
+    }
+
+    let tab = session.tab.clone();
+    match tokio::task::spawn_blocking(move || hands::screenshot_base64(&tab))
+        .await
+        .unwrap()
+    {
+        Ok(jpeg) => {
+            eprintln!("[Agent] DOM snapshot is empty; sending a screenshot instead.");
+            brain.attach_screenshot(jpeg);
+        }
+        Err(e) => eprintln!("[Agent] Warning: Vision fallback screenshot failed: {}", e),
+    }
+}
+
+/// Get a working tab back after repeated step errors: a fresh tab if the
+/// browser still responds, otherwise a relaunched browser. Returns what was
+/// done.
+async fn recover_browser(session: &mut hands::BrowserSession) -> Result<&'static str> {
+    eprintln!("[Agent] Recovering browser after repeated step errors...");
+    if session.new_tab().is_ok() {
+        return Ok("the agent opened a fresh tab");
+    }
+    eprintln!("[Agent] Browser not responding, relaunching Chrome...");
+    // Release the old browser (and its profile lock) first
+    session.close();
+    *session = tokio::task::spawn_blocking(hands::BrowserSession::launch).await??;
+    Ok("the agent relaunched the browser")
+}
+
+/// Append extractions to RESULTS_FILE as JSON lines, as soon as they are
+/// produced, so a crash mid-task keeps everything gathered so far.
+fn append_results(extracted: &[types::Extraction], session_id: &str) -> Result<()> {
+    use std::io::Write;
+
+    let Some(path) = std::env::var("RESULTS_FILE").ok().filter(|p| !p.is_empty()) else {
+        return Ok(());
+    };
+    if extracted.is_empty() {
+        return Ok(());
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    for extraction in extracted {
+        let line = serde_json::json!({
+            "session_id": session_id,
+            "label": extraction.label,
+            "content": extraction.content,
+        });
+        writeln!(file, "{}", line)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Whether `step` may type into a password field, via its selector or any fallback.
+async fn targets_password_field(session: &hands::BrowserSession, step: &Step) -> bool {
+    match step {
+        Step::TypeInto {
+            selector,
+            fallbacks,
+            ..
+        } => {
+            // Any fallback may be the one that resolves, so check them all
+            let tab = session.tab.clone();
+            let candidates: Vec<String> = std::iter::once(selector)
+                .chain(fallbacks)
+                .cloned()
+                .collect();
+            tokio::task::spawn_blocking(move || {
+                candidates
+                    .iter()
+                    .any(|sel| crate::dom::is_password_field(&tab, sel))
+            })
+            .await
+            .unwrap_or(false)
+        }
+        Step::TypeByLabel { label, .. } => {
+            let tab = session.tab.clone();
+            let label = label.clone();
+            tokio::task::spawn_blocking(move || crate::dom::is_password_label(&tab, &label))
+                .await
+                .unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
+/// Execute one step against the session and observe the result.
+///
+/// Tab steps act on the session itself; everything else runs on the current
+/// tab in a blocking task. Follows any tab the step opened, and publishes
+/// anything the step extracted.
+async fn perform_step(
+    session: &mut hands::BrowserSession,
+    step: &Step,
+    events: &tokio::sync::broadcast::Sender<AgentEvent>,
+) -> types::PageState {
+    // Handle NewTab specially (requires session, not just tab)
+    if let Step::NewTab = step {
+        eprintln!("[Agent] Opening new tab...");
+        if let Err(e) = session.new_tab() {
+            eprintln!("[Agent] Failed to open new tab: {}", e);
+        }
+    }
+    if let Step::SwitchToOldestTab = step {
+        eprintln!("[Agent] Switching back to the task's first tab...");
+        session.switch_to_home_tab();
+    }
+    // History lives on the session, so resolve it to a plain Navigate
+    let resolved;
+    let step = match step {
+        Step::GoToHistory { title_contains } => match session.find_in_history(title_contains) {
+            Some(url) => {
+                resolved = Step::Navigate { url };
+                &resolved
+            }
+            None => step,
+        },
+        _ => step,
+    };
+
+    // Execute in a blocking context so we don't stall tokio
+    let tabs_before = session.tab_count();
+    let tab = session.tab.clone();
+    let downloads = session.downloads.clone();
+    let step_clone = step.clone();
+    let mut page_state = tokio::task::spawn_blocking(move || {
+        let mut extracted = Vec::new();
+        let mut notes = Vec::new();
+        let mut error = None;
+
+        match execute_step_on_tab(&tab, &downloads, &step_clone, &mut extracted, &mut notes) {
+            Ok(()) => {}
+            Err(e) => error = Some(format!("{:#}", e)),
+        }
+
+        let mut page_state = capture_page_state(&tab, extracted, error);
+        page_state.notes = notes;
+        page_state
+    })
+    .await
+    .unwrap();
+
+    // A click may have opened a popup or target=_blank tab; follow it
+    if session.follow_new_tab(tabs_before) {
+        eprintln!("[Agent] New tab opened, switching to it.");
+        let tab = session.tab.clone();
+        let types::PageState {
+            extracted,
+            error,
+            notes,
+            ..
+        } = page_state;
+        page_state =
+            tokio::task::spawn_blocking(move || capture_page_state(&tab, extracted, error))
+                .await
+                .unwrap();
+        page_state.notes = notes;
+        page_state.notes.push(
+            "The last step opened a new tab; you are now on it. \
+             Use SwitchToOldestTab to go back."
+                .to_string(),
+        );
+    }
+
+    session.record_visit(&page_state.title, &page_state.url);
+    page_state.recent_pages = session.history().to_vec();
+
+    for extraction in &page_state.extracted {
+        let _ = events.send(AgentEvent::Extracted {
+            label: extraction.label.clone(),
+            content: extraction.content.clone(),
+        });
+    }
+
+    page_state
+}
+
+/// Replay a saved macro step by step, without asking the LLM.
+///
+/// Steps that fail (e.g. a selector that no longer matches) are reported
+/// and skipped rather than ending the replay, but any failure marks the
+/// replay as failed.
+async fn replay_macro(
+    session: &mut hands::BrowserSession,
+    name: &str,
+    ui: &face::AppState,
+) -> TaskOutcome {
+    let events = &ui.event_tx;
+    let steps = match macros::load(name) {
+        Ok(steps) => steps,
+        Err(e) => {
+            let message = format!("{:#}", e);
+            eprintln!("[Agent] Replay failed: {}", message);
+            let _ = events.send(AgentEvent::TaskError {
+                message: message.clone(),
+                session_id: ui.session_id.clone(),
+            });
+            let _ = events.send(AgentEvent::Ready);
+            return TaskOutcome::Failed { message };
+        }
+    };
+
+    eprintln!("[Agent] Replaying macro '{}' ({} steps)", name, steps.len());
+    ui.stats.tasks.fetch_add(1, Ordering::Relaxed);
+    session.clear_history();
+    if let Err(e) = session.new_tab() {
+        eprintln!("[Agent] Warning: Failed to open new tab for replay: {}", e);
+    }
+
+    let started = std::time::Instant::now();
+    let mut failed = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        let number = i + 1;
+        ui.stats.steps.fetch_add(1, Ordering::Relaxed);
+        let _ = events.send(AgentEvent::Step {
+            number,
+            limit: steps.len(),
+            kind: step.kind(),
+            description: format!("{:?}", step),
+            reason: None,
+        });
+
+        // Redacted values were never saved, so there is nothing to type
+        if step.secret() == Some(types::REDACTED) {
+            eprintln!("[Agent] Replay step {} skipped: value was redacted", number);
+            let _ = events.send(AgentEvent::StepError {
+                message: format!(
+                    "Step {} skipped: its value was redacted when recorded",
+                    number
+                ),
+            });
+            failed.push(number.to_string());
+            continue;
+        }
+
+        if let Some(err) = perform_step(session, step, events).await.error {
+            eprintln!("[Agent] Replay step {} skipped: {}", number, err);
+            let _ = events.send(AgentEvent::StepError {
+                message: format!("Step {} skipped: {}", number, err),
+            });
+            failed.push(number.to_string());
+        }
+    }
+
+    if !failed.is_empty() {
+        let message = format!(
+            "Replay of macro '{}' failed: {} of {} steps failed ({})",
+            name,
+            failed.len(),
+            steps.len(),
+            failed.join(", ")
+        );
+        eprintln!("[Agent] {}", message);
+        let _ = events.send(AgentEvent::TaskError {
+            message: message.clone(),
+            session_id: ui.session_id.clone(),
+        });
+        let _ = events.send(AgentEvent::Ready);
+        return TaskOutcome::Failed { message };
+    }
+
+    let summary = format!("Replayed macro '{}' ({} steps)", name, steps.len());
+    eprintln!("[Agent] {}", summary);
+    let _ = events.send(AgentEvent::TaskComplete {
+        summary: summary.clone(),
+        session_id: ui.session_id.clone(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        steps: steps.len(),
+    });
+    let _ = events.send(AgentEvent::Ready);
+    TaskOutcome::Complete { summary }
+}
+
+/// Snapshot the tab into a PageState for the Brain.
+fn capture_page_state(
+    tab: &std::sync::Arc<headless_chrome::Tab>,
+    extracted: Vec<types::Extraction>,
+    error: Option<String>,
+) -> types::PageState {
+    let url = crate::dom::get_current_url(tab).unwrap_or_else(|_| "unknown".into());
+    let title = crate::dom::get_page_title(tab).unwrap_or_else(|_| "untitled".into());
+    let dom_snapshot = crate::dom::capture_dom_snapshot(tab).unwrap_or_else(|_| String::new());
+
+    types::PageState {
+        url,
+        http_status: hands::page_status(tab),
+        title,
+        dom_snapshot,
+        extracted,
+        error,
+        notes: Vec::new(),
+        recent_pages: Vec::new(),
+        console_errors: hands::console_errors(tab),
+    }
+}
+
+/// Flag the current document so `wait_for_navigation` can tell whether the
+/// next action started loading a new page.
+fn watch_for_navigation(tab: &headless_chrome::Tab) -> Result<()> {
+    tab.evaluate(
+        "window.__agentNav = 'none'; \
+         window.addEventListener('beforeunload', () => { window.__agentNav = 'pending'; }, {once: true});",
+        false,
+    )?;
+    Ok(())
+}
+
+/// Wait for a navigation started since `watch_for_navigation` to finish
+/// loading, or settle briefly if nothing navigated (e.g. in-page updates).
+fn wait_for_navigation(tab: &headless_chrome::Tab) {
+    use std::time::{Duration, Instant};
+
+    const NAVIGATION_TIMEOUT: Duration = Duration::from_secs(15);
+    let started = Instant::now();
+    std::thread::sleep(Duration::from_millis(300));
+
+    loop {
+        // A fresh document has no marker; evaluation fails mid-navigation
+        let state = tab
+            .evaluate(
+                "window.__agentNav === undefined ? document.readyState : window.__agentNav",
+                false,
+            )
+            .ok()
+            .and_then(|r| r.value)
+            .and_then(|v| v.as_str().map(String::from))
+            .unwrap_or_else(|| "pending".to_string());
+
+        match state.as_str() {
+            "none" => {
+                // No page load; give in-page handlers the old settle time
+                std::thread::sleep(Duration::from_millis(700));
+                return;
+            }
+            "complete" => {
+                let _ = tab.wait_for_element("body");
+                return;
+            }
+            _ if started.elapsed() >= NAVIGATION_TIMEOUT => {
+                eprintln!(
+                    "[Agent] Navigation still loading after {:?}",
+                    NAVIGATION_TIMEOUT
+                );
+                return;
+            }
+            _ => std::thread::sleep(Duration::from_millis(200)),
+        }
+    }
+}
+
+/// Character cap for extracted text, read from EXTRACT_MAX_CHARS on first use.
+fn extract_max_chars() -> usize {
+    static LIMIT: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+    *LIMIT.get_or_init(|| {
+        std::env::var("EXTRACT_MAX_CHARS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(types::EXTRACT_MAX_CHARS)
+    })
+}
+
+/// Hosts from DOMAIN_BLOCKLIST the agent must never navigate to.
+fn domain_blocklist() -> &'static [String] {
+    static BLOCKED: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
+    BLOCKED.get_or_init(|| {
+        std::env::var("DOMAIN_BLOCKLIST")
+            .unwrap_or_default()
+            .split(',')
+            .map(|d| d.trim().trim_start_matches('.').to_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect()
+    })
+}
+
+/// AUTO_ACTIVATE=1: bring the tab to the front before every click or input.
+fn auto_activate() -> bool {
+    static ENABLED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    *ENABLED.get_or_init(|| std::env::var("AUTO_ACTIVATE").is_ok_and(|v| v == "1"))
+}
+
+/// Trim extracted text to `extract_max_chars`.
+fn cap_extract(content: &str) -> String {
+    content.chars().take(extract_max_chars()).collect()
+}
+
+/// Find `selector`, or else the first of `fallbacks` that matches, noting
+/// which one was used. Returns the element and the selector that found it.
+fn find_with_fallbacks<'a>(
+    tab: &'a headless_chrome::Tab,
+    selector: &'a str,
+    fallbacks: &'a [String],
+    notes: &mut Vec<String>,
+) -> Result<(headless_chrome::Element<'a>, &'a str)> {
+    let mut first_err = None;
+    for candidate in std::iter::once(selector).chain(fallbacks.iter().map(String::as_str)) {
+        match tab.find_element(candidate) {
+            Ok(el) => {
+                if candidate != selector {
+                    notes.push(format!(
+                        "'{}' matched nothing; used fallback '{}'.",
+                        selector, candidate
+                    ));
+                }
+                return Ok((el, candidate));
+            }
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+    match first_err {
+        Some(e) if fallbacks.is_empty() => Err(e),
+        _ => anyhow::bail!(
+            "No element matches '{}' or any of its {} fallback(s)",
+            selector,
+            fallbacks.len()
+        ),
+    }
+}
+
+/// Write a PNG to screenshots/<prefix>-<millis>.png and return its path.
+fn save_screenshot(prefix: &str, png: &[u8]) -> Result<std::path::PathBuf> {
+    let dir = std::env::current_dir()?.join("screenshots");
+    std::fs::create_dir_all(&dir)?;
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis();
+    let path = dir.join(format!("{}-{}.png", prefix, millis));
+    std::fs::write(&path, png)?;
+    Ok(path)
 }
 
 /// Execute a step using just the Arc<Tab> (so it can run in spawn_blocking).
+///
+/// Anything the Brain should know beyond the page itself goes in `notes`.
 fn execute_step_on_tab(
     tab: &std::sync::Arc<headless_chrome::Tab>,
+    downloads: &hands::Downloads,
     step: &Step,
     extracted: &mut Vec<types::Extraction>,
+    notes: &mut Vec<String>,
 ) -> Result<()> {
+    use headless_chrome::protocol::cdp::Page;
     use std::time::Duration;
 
+    // Background tabs can ignore focus-dependent events
+    if auto_activate()
+        && step.is_interactive()
+        && let Err(e) = tab.bring_to_front()
+    {
+        eprintln!("[Agent] Warning: Could not bring tab to front: {}", e);
+    }
+
     match step {
+        Step::FollowLink { href_contains } => {
+            let js_needle = serde_json::to_string(href_contains)?;
+            let result = tab.evaluate(
+                &format!(
+                    "(() => {{ const link = [...document.querySelectorAll('a[href]')].find(a => \
+                     a.href.includes({js_needle}) && a.getClientRects().length > 0); \
+                     return link ? link.href : null; }})()"
+                ),
+                false,
+            )?;
+            let Some(url) = result.value.and_then(|v| v.as_str().map(String::from)) else {
+                anyhow::bail!("No visible link's URL contains '{}'", href_contains);
+            };
+            notes.push(format!("Followed link to {}.", url));
+            // Navigate so DOMAIN_BLOCKLIST and the same-page check apply
+            let navigate = Step::Navigate { url };
+            execute_step_on_tab(tab, downloads, &navigate, extracted, notes)?;
+        }
+        Step::RightClick { selector } => {
+            let js_sel = serde_json::to_string(selector)?;
+            let result = tab.evaluate(
+                &format!(
+                    "(() => {{ const el = document.querySelector({js_sel}); \
+                     if (!el) return false; \
+                     el.scrollIntoView({{block: 'center'}}); \
+                     const r = el.getBoundingClientRect(); \
+                     const init = {{bubbles: true, cancelable: true, view: window, button: 2, buttons: 2, \
+                       clientX: r.x + r.width / 2, clientY: r.y + r.height / 2}}; \
+                     for (const type of ['mousedown', 'mouseup', 'contextmenu']) \
+                       el.dispatchEvent(new MouseEvent(type, init)); \
+                     return true; }})()"
+                ),
+                false,
+            )?;
+            if !result.value.and_then(|v| v.as_bool()).unwrap_or(false) {
+                anyhow::bail!("No element matches '{}'", selector);
+            }
+            // Give the menu time to render into the next snapshot
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        Step::ScrollIntoView { selector } => {
+            if !crate::dom::scroll_into_view(tab, selector)? {
+                anyhow::bail!("No element matches '{}'", selector);
+            }
+        }
+        Step::DismissModal => {
+            notes.push(crate::dom::dismiss_modal(tab)?);
+        }
+        Step::Activate => {
+            tab.bring_to_front()?;
+        }
         Step::Navigate { url } => {
+            if crate::dom::is_blocked(url, domain_blocklist()) {
+                anyhow::bail!("That domain is blocked: {} is on DOMAIN_BLOCKLIST", url);
+            }
+            if crate::dom::same_page(&tab.get_url(), url) {
+                notes.push(format!(
+                    "Already on {}; Navigate was skipped and the page was not reloaded.",
+                    url
+                ));
+                return Ok(());
+            }
             tab.navigate_to(url)?;
             tab.wait_for_element("body")?;
             std::thread::sleep(Duration::from_millis(1500));
@@ -165,24 +1005,223 @@ fn execute_step_on_tab(
         } => {
             tab.wait_for_element_with_custom_timeout(selector, Duration::from_millis(*timeout_ms))?;
         }
-        Step::TypeInto { selector, text } => {
-            let el = tab.find_element(selector)?;
+        Step::WaitForGone {
+            selector,
+            timeout_ms,
+        } => {
+            let js_sel = serde_json::to_string(selector)?;
+            let check = format!(
+                "(() => {{ const el = document.querySelector({js_sel}); \
+                 return !el || !el.getClientRects().length \
+                   || getComputedStyle(el).visibility === 'hidden'; }})()"
+            );
+            let deadline = std::time::Instant::now() + Duration::from_millis(*timeout_ms);
+            loop {
+                let gone = tab
+                    .evaluate(&check, false)?
+                    .value
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                if gone {
+                    break;
+                }
+                if std::time::Instant::now() >= deadline {
+                    anyhow::bail!("'{}' still visible after {}ms", selector, timeout_ms);
+                }
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        }
+        Step::Assert {
+            selector,
+            text_contains,
+        } => {
+            if selector.is_none() && text_contains.is_none() {
+                anyhow::bail!("Assertion failed: Assert needs a selector or text_contains");
+            }
+            let js_sel = serde_json::to_string(selector)?;
+            let js_text = serde_json::to_string(text_contains)?;
+            let check = format!(
+                "(() => {{ const sel = {js_sel}, want = {js_text}; \
+                 const el = sel === null ? document.body : document.querySelector(sel); \
+                 if (!el) return 'missing'; \
+                 const text = el.innerText || el.textContent || ''; \
+                 return want === null || text.includes(want) ? 'ok' : 'no-text'; }})()"
+            );
+            let result = tab
+                .evaluate(&check, false)?
+                .value
+                .and_then(|v| v.as_str().map(String::from));
+            let scope = selector.as_deref().unwrap_or("the page");
+            match result.as_deref() {
+                Some("ok") => {}
+                Some("missing") => {
+                    anyhow::bail!("Assertion failed: no element matches '{}'", scope)
+                }
+                _ => anyhow::bail!(
+                    "Assertion failed: '{}' not found in {}",
+                    text_contains.as_deref().unwrap_or_default(),
+                    scope
+                ),
+            }
+        }
+        Step::WaitForStable {
+            selector,
+            timeout_ms,
+        } => {
+            // Stable once the box hasn't moved or resized for this long
+            const SETTLE: Duration = Duration::from_millis(300);
+
+            let js_sel = serde_json::to_string(selector)?;
+            let measure = format!(
+                "(() => {{ const el = document.querySelector({js_sel}); \
+                 if (!el) return null; \
+                 const r = el.getBoundingClientRect(); \
+                 return [r.x, r.y, r.width, r.height].map(Math.round).join(','); }})()"
+            );
+            let deadline = std::time::Instant::now() + Duration::from_millis(*timeout_ms);
+            let mut last: Option<(String, std::time::Instant)> = None;
+            loop {
+                let Some(rect) = tab
+                    .evaluate(&measure, false)?
+                    .value
+                    .and_then(|v| v.as_str().map(String::from))
+                else {
+                    anyhow::bail!("No element matches '{}'", selector);
+                };
+                match &last {
+                    Some((prev, since)) if *prev == rect => {
+                        if since.elapsed() >= SETTLE {
+                            break;
+                        }
+                    }
+                    _ => last = Some((rect, std::time::Instant::now())),
+                }
+                if std::time::Instant::now() >= deadline {
+                    anyhow::bail!("'{}' still moving after {}ms", selector, timeout_ms);
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+        Step::WaitForUrl {
+            pattern,
+            timeout_ms,
+        } => {
+            let deadline = std::time::Instant::now() + Duration::from_millis(*timeout_ms);
+            while !crate::dom::url_matches(&tab.get_url(), pattern) {
+                if std::time::Instant::now() >= deadline {
+                    anyhow::bail!(
+                        "URL '{}' did not match '{}' within {}ms",
+                        tab.get_url(),
+                        pattern,
+                        timeout_ms
+                    );
+                }
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        }
+        Step::TypeInto {
+            selector,
+            text,
+            fallbacks,
+        } => {
+            let (el, selector) = find_with_fallbacks(tab, selector, fallbacks, notes)?;
+            crate::dom::scroll_into_view(tab, selector)?;
             el.click()?;
-            let js_sel = selector.replace('\'', "\\'");
-            tab.evaluate(
-                &format!("document.querySelector('{js_sel}').value = ''"),
-                false,
-            )?;
+            crate::dom::set_value(tab, selector, "")?;
+            tab.type_str(text)?;
+            // Controlled inputs can re-render away keystrokes; set it directly
+            // only then, so masks, maxlength and formatters keep what they took
+            if !text.is_empty()
+                && crate::dom::field_value(tab, selector)?.is_some_and(|v| v.is_empty())
+            {
+                crate::dom::set_value(tab, selector, text)?;
+            }
+        }
+        Step::TypeByLabel { label, text } => {
+            if !crate::dom::focus_by_label(tab, label)? {
+                anyhow::bail!("No form field labelled '{}'", label);
+            }
             tab.type_str(text)?;
         }
-        Step::Click { selector } => {
-            let el = tab.find_element(selector)?;
+        Step::ClearInput { selector } => {
+            if !crate::dom::set_value(tab, selector, "")? {
+                anyhow::bail!("No element matches '{}'", selector);
+            }
+        }
+        Step::Click {
+            selector,
+            fallbacks,
+        } => {
+            let (el, selector) = find_with_fallbacks(tab, selector, fallbacks, notes)?;
+            crate::dom::scroll_into_view(tab, selector)?;
             el.click()?;
             std::thread::sleep(Duration::from_millis(1000));
         }
+        Step::ClickNth { selector, index } => {
+            let js_sel = serde_json::to_string(selector)?;
+            let result = tab.evaluate(
+                &format!(
+                    "(() => {{ const els = document.querySelectorAll({js_sel}); \
+                     if (els.length > {index}) els[{index}].click(); return els.length; }})()"
+                ),
+                false,
+            )?;
+            let count = result.value.and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+            if *index >= count {
+                anyhow::bail!(
+                    "ClickNth index {} out of range: '{}' matched {} element(s)",
+                    index,
+                    selector,
+                    count
+                );
+            }
+            std::thread::sleep(Duration::from_millis(1000));
+        }
         Step::PressKey { key } => {
+            watch_for_navigation(tab)?;
             tab.press_key(key)?;
-            std::thread::sleep(Duration::from_millis(1000));
+            wait_for_navigation(tab);
+        }
+        Step::SubmitForm { selector } => {
+            let js_sel = serde_json::to_string(selector)?;
+            watch_for_navigation(tab)?;
+            // requestSubmit runs validation and submit handlers, unlike submit()
+            let result = tab.evaluate(
+                &format!(
+                    "(() => {{ const sel = {js_sel}; \
+                     let el = null; try {{ el = document.querySelector(sel); }} catch (e) {{}} \
+                     const form = el ? (el instanceof HTMLFormElement ? el : el.form || el.closest('form')) \
+                                     : document.forms[sel]; \
+                     if (!form) return false; form.requestSubmit(); return true; }})()"
+                ),
+                false,
+            )?;
+            if !result.value.and_then(|v| v.as_bool()).unwrap_or(false) {
+                anyhow::bail!("No form found for '{}'", selector);
+            }
+            wait_for_navigation(tab);
+        }
+        Step::SetChecked { selector, checked } => {
+            let js_sel = serde_json::to_string(selector)?;
+            // Clicking toggles, so only click when the state is wrong
+            let result = tab.evaluate(
+                &format!(
+                    "(() => {{ const el = document.querySelector({js_sel}); \
+                     if (!el) return null; \
+                     if (el.checked !== {checked}) el.click(); \
+                     if (el.checked !== {checked}) {{ \
+                       el.checked = {checked}; \
+                       el.dispatchEvent(new Event('change', {{bubbles: true}})); \
+                     }} \
+                     return el.checked; }})()"
+                ),
+                false,
+            )?;
+            match result.value.and_then(|v| v.as_bool()) {
+                Some(state) if state == *checked => {}
+                Some(_) => anyhow::bail!("'{}' could not be set to checked={}", selector, checked),
+                None => anyhow::bail!("No element matches '{}'", selector),
+            }
         }
         Step::Extract { selector, label } => {
             let js_sel = selector.replace('\'', "\\'");
@@ -196,10 +1235,324 @@ fn execute_step_on_tab(
                 .unwrap_or_default();
             extracted.push(types::Extraction {
                 label: label.clone(),
-                content: content.chars().take(2000).collect(),
+                content: cap_extract(&content),
+            });
+        }
+        Step::ExtractLinks { label } => {
+            let result = tab.evaluate(
+                "(() => { const seen = new Set(); const links = []; \
+                 for (const a of document.querySelectorAll('a[href]')) { \
+                   if (links.length >= 100) break; \
+                   if (seen.has(a.href) || !a.href.startsWith('http')) continue; \
+                   seen.add(a.href); \
+                   links.push({text: a.innerText.replace(/\\s+/g, ' ').trim(), href: a.href}); \
+                 } return JSON.stringify(links); })()",
+                false,
+            )?;
+            let content = result
+                .value
+                .and_then(|v| v.as_str().map(String::from))
+                .unwrap_or_default();
+            extracted.push(types::Extraction {
+                label: label.clone(),
+                content: cap_extract(&content),
+            });
+        }
+        Step::ExtractTable { selector, label } => {
+            let js_sel = serde_json::to_string(selector)?;
+            // Spanned cells are padded with "" so columns stay roughly aligned
+            let result = tab.evaluate(
+                &format!(
+                    "(() => {{ const el = document.querySelector({js_sel}); \
+                     const table = el && (el.tagName === 'TABLE' ? el : el.querySelector('table')); \
+                     if (!table) return null; \
+                     const rows = []; let cells = 0; \
+                     for (const tr of table.rows) {{ \
+                       const row = []; \
+                       for (const td of tr.cells) {{ \
+                         row.push(td.innerText.replace(/\\s+/g, ' ').trim()); \
+                         for (let i = 1; i < td.colSpan; i++) row.push(''); \
+                       }} \
+                       cells += row.length; \
+                       if (cells > 500) break; \
+                       rows.push(row); \
+                     }} \
+                     return JSON.stringify(rows); }})()"
+                ),
+                false,
+            )?;
+            let Some(content) = result.value.and_then(|v| v.as_str().map(String::from)) else {
+                anyhow::bail!("No table found for '{}'", selector);
+            };
+            extracted.push(types::Extraction {
+                label: label.clone(),
+                content: cap_extract(&content),
+            });
+        }
+        Step::ScreenshotElement { selector } => {
+            let el = tab
+                .find_element(selector)
+                .map_err(|e| anyhow::anyhow!("No element matches '{}': {}", selector, e))?;
+            crate::dom::scroll_into_view(tab, selector)?;
+            let png = el
+                .capture_screenshot(Page::CaptureScreenshotFormatOption::Png)
+                .map_err(|e| anyhow::anyhow!("'{}' is not visible: {}", selector, e))?;
+            let path = save_screenshot("element", &png)?;
+            extracted.push(types::Extraction {
+                label: "screenshot".to_string(),
+                content: path.display().to_string(),
+            });
+        }
+        Step::FullPageScreenshot => {
+            use headless_chrome::protocol::cdp::Emulation;
+
+            let max_height: u32 = std::env::var("FULL_PAGE_MAX_HEIGHT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10_000);
+            let size = tab
+                .evaluate(
+                    "JSON.stringify([document.documentElement.clientWidth, \
+                     document.documentElement.scrollHeight])",
+                    false,
+                )?
+                .value
+                .and_then(|v| v.as_str().map(String::from))
+                .unwrap_or_default();
+            let (width, height): (u32, u32) = serde_json::from_str(&size)?;
+            let height = height.min(max_height);
+
+            // Grow the viewport to the page's height for one capture
+            tab.call_method(Emulation::SetDeviceMetricsOverride {
+                width,
+                height,
+                device_scale_factor: 1.0,
+                mobile: false,
+                scale: None,
+                screen_width: None,
+                screen_height: None,
+                position_x: None,
+                position_y: None,
+                dont_set_visible_size: None,
+                screen_orientation: None,
+                viewport: None,
+                display_feature: None,
+                device_posture: None,
+            })?;
+            let png =
+                tab.capture_screenshot(Page::CaptureScreenshotFormatOption::Png, None, None, true);
+            tab.call_method(Emulation::ClearDeviceMetricsOverride(None))?;
+
+            let path = save_screenshot("page", &png?)?;
+            extracted.push(types::Extraction {
+                label: "screenshot".to_string(),
+                content: path.display().to_string(),
+            });
+        }
+        Step::GetAttribute {
+            selector,
+            attribute,
+            label,
+        } => {
+            // Same rule as the DOM snapshot: never read back a password
+            if attribute.eq_ignore_ascii_case("value")
+                && crate::dom::is_password_field(tab, selector)
+            {
+                anyhow::bail!(
+                    "Refusing to read the value of password field '{}'",
+                    selector
+                );
+            }
+            let js_sel = serde_json::to_string(selector)?;
+            let js_attr = serde_json::to_string(attribute)?;
+            // Live state lives in properties; the attribute only holds the initial value
+            let result = tab.evaluate(
+                &format!(
+                    "(() => {{ const el = document.querySelector({js_sel}); \
+                     if (!el) return null; \
+                     const name = {js_attr}; \
+                     const value = (name === 'value' || name === 'checked') && name in el \
+                       ? String(el[name]) : el.getAttribute(name); \
+                     return JSON.stringify(value); }})()"
+                ),
+                false,
+            )?;
+            let Some(json) = result.value.and_then(|v| v.as_str().map(String::from)) else {
+                anyhow::bail!("No element matches '{}'", selector);
+            };
+            let content = match serde_json::from_str::<Option<String>>(&json)? {
+                Some(value) => value,
+                None => {
+                    notes.push(format!("'{}' has no '{}' attribute.", selector, attribute));
+                    String::new()
+                }
+            };
+            extracted.push(types::Extraction {
+                label: label.clone(),
+                content: cap_extract(&content),
+            });
+        }
+        Step::PerfMetrics { label } => {
+            // Navigation Timing Level 2, falling back to the legacy API
+            let result = tab.evaluate(
+                "(() => { \
+                 const round = v => Math.round(v); \
+                 const nav = performance.getEntriesByType('navigation')[0]; \
+                 if (nav) return JSON.stringify({ \
+                   ttfb: round(nav.responseStart - nav.startTime), \
+                   dom_content_loaded: round(nav.domContentLoadedEventEnd - nav.startTime), \
+                   load: round(nav.loadEventEnd - nav.startTime), \
+                   transfer_bytes: nav.transferSize }); \
+                 const t = performance.timing; \
+                 return JSON.stringify({ \
+                   ttfb: t.responseStart - t.navigationStart, \
+                   dom_content_loaded: t.domContentLoadedEventEnd - t.navigationStart, \
+                   load: t.loadEventEnd - t.navigationStart }); })()",
+                false,
+            )?;
+            let content = result
+                .value
+                .and_then(|v| v.as_str().map(String::from))
+                .unwrap_or_default();
+            extracted.push(types::Extraction {
+                label: label.clone(),
+                content: cap_extract(&content),
             });
         }
-        Step::Screenshot | Step::Done { .. } | Step::NewTab => {}
+        Step::CountElements { selector, label } => {
+            let js_sel = serde_json::to_string(selector)?;
+            let result = tab.evaluate(
+                &format!("document.querySelectorAll({js_sel}).length"),
+                false,
+            )?;
+            let count = result.value.and_then(|v| v.as_u64()).unwrap_or(0);
+            extracted.push(types::Extraction {
+                label: label.clone(),
+                content: count.to_string(),
+            });
+        }
+        Step::WaitForDownload { timeout_ms } => {
+            let path = downloads.wait_for_new(Duration::from_millis(*timeout_ms))?;
+            extracted.push(types::Extraction {
+                label: "download".to_string(),
+                content: path.display().to_string(),
+            });
+        }
+        Step::ReadDownload { timeout_ms, label } => {
+            let path = downloads.wait_for_new(Duration::from_millis(*timeout_ms))?;
+            let max_bytes = std::env::var("DOWNLOAD_MAX_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(100_000);
+            extracted.push(types::Extraction {
+                label: label.clone(),
+                content: hands::read_download_text(&path, max_bytes)?,
+            });
+            notes.push(format!("Read {}.", path.display()));
+        }
+        Step::PageInfo { label } => {
+            extracted.push(types::Extraction {
+                label: label.clone(),
+                content: crate::dom::page_info(tab)?,
+            });
+        }
+        Step::GetStorage { key, label } => {
+            let js_key = serde_json::to_string(key)?;
+            let result = tab.evaluate(
+                &format!("window.localStorage.getItem({js_key}) ?? ''"),
+                false,
+            )?;
+            let content = result
+                .value
+                .and_then(|v| v.as_str().map(String::from))
+                .unwrap_or_default();
+            extracted.push(types::Extraction {
+                label: label.clone(),
+                content: cap_extract(&content),
+            });
+        }
+        Step::SetStorage { key, value } => {
+            let js_key = serde_json::to_string(key)?;
+            let js_value = serde_json::to_string(value)?;
+            tab.evaluate(
+                &format!("window.localStorage.setItem({js_key}, {js_value})"),
+                false,
+            )?;
+        }
+        Step::SetWindowBounds {
+            width,
+            height,
+            maximize,
+        } => {
+            use headless_chrome::types::Bounds;
+
+            if *maximize {
+                tab.set_bounds(Bounds::Maximized)?;
+                notes.push("Window maximized.".to_string());
+            } else {
+                let (Some(width), Some(height)) = (width, height) else {
+                    anyhow::bail!("SetWindowBounds needs width and height unless maximize is true");
+                };
+                tab.set_bounds(Bounds::Normal {
+                    left: None,
+                    top: None,
+                    width: Some(*width as f64),
+                    height: Some(*height as f64),
+                })?;
+                notes.push(format!("Window resized to {}x{}.", width, height));
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        Step::GrantPermission { name } => {
+            // Opaque origins (about:blank, data:) report "null"; granting
+            // there would mean granting to every site
+            let Some(origin) = tab
+                .evaluate("location.origin", false)?
+                .value
+                .and_then(|v| v.as_str().map(String::from))
+                .filter(|o| o != "null")
+            else {
+                anyhow::bail!("No page origin to grant '{}' to", name);
+            };
+            hands::grant_permissions(tab, &[name.as_str()], Some(origin.clone()))?;
+            notes.push(format!("Granted '{}' permission to {}.", name, origin));
+        }
+        Step::ClearCookies { current_origin } => {
+            use headless_chrome::protocol::cdp::Network;
+
+            let content = if *current_origin {
+                let cookies: Vec<Network::DeleteCookies> = tab
+                    .get_cookies()?
+                    .into_iter()
+                    .map(|c| Network::DeleteCookies {
+                        name: c.name,
+                        url: None,
+                        domain: Some(c.domain),
+                        path: Some(c.path),
+                        partition_key: None,
+                    })
+                    .collect();
+                let count = cookies.len();
+                tab.delete_cookies(cookies)?;
+                format!("Cleared {} cookie(s) for {}", count, tab.get_url())
+            } else {
+                tab.call_method(Network::ClearBrowserCookies(None))?;
+                "Cleared all browser cookies".to_string()
+            };
+            extracted.push(types::Extraction {
+                label: "cookies".to_string(),
+                content,
+            });
+        }
+        // perform_step turns a GoToHistory that matched into a Navigate
+        Step::GoToHistory { title_contains } => {
+            anyhow::bail!("No recent page title contains '{}'", title_contains);
+        }
+        Step::Screenshot
+        | Step::Done { .. }
+        | Step::NewTab
+        | Step::SwitchToOldestTab
+        | Step::ClearExtractions => {}
     }
 
     Ok(())
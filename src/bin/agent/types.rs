@@ -1,28 +1,349 @@
+use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
 
 /// A single atomic step the LLM asks the agent to perform.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "action")]
 pub enum Step {
-    Navigate { url: String },
-    WaitFor { selector: String, timeout_ms: u64 },
-    TypeInto { selector: String, text: String },
-    Click { selector: String },
-    PressKey { key: String },
-    Extract { selector: String, label: String },
+    Navigate {
+        url: String,
+    },
+    FollowLink {
+        href_contains: String,
+    },
+    WaitFor {
+        selector: String,
+        timeout_ms: u64,
+    },
+    WaitForGone {
+        selector: String,
+        timeout_ms: u64,
+    },
+    WaitForUrl {
+        pattern: String,
+        timeout_ms: u64,
+    },
+    WaitForStable {
+        selector: String,
+        timeout_ms: u64,
+    },
+    TypeInto {
+        selector: String,
+        text: String,
+        /// Alternative selectors tried in order if `selector` matches nothing.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fallbacks: Vec<String>,
+    },
+    TypeByLabel {
+        label: String,
+        text: String,
+    },
+    ClearInput {
+        selector: String,
+    },
+    Click {
+        selector: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fallbacks: Vec<String>,
+    },
+    ClickNth {
+        selector: String,
+        index: usize,
+    },
+    RightClick {
+        selector: String,
+    },
+    PressKey {
+        key: String,
+    },
+    SubmitForm {
+        selector: String,
+    },
+    SetChecked {
+        selector: String,
+        checked: bool,
+    },
+    Extract {
+        selector: String,
+        label: String,
+    },
+    ExtractLinks {
+        label: String,
+    },
+    ExtractTable {
+        selector: String,
+        label: String,
+    },
+    CountElements {
+        selector: String,
+        label: String,
+    },
+    GetAttribute {
+        selector: String,
+        attribute: String,
+        label: String,
+    },
+    PerfMetrics {
+        label: String,
+    },
+    ClearExtractions,
     Screenshot,
-    Done { summary: String },
+    FullPageScreenshot,
+    ScreenshotElement {
+        selector: String,
+    },
+    PageInfo {
+        label: String,
+    },
+    WaitForDownload {
+        timeout_ms: u64,
+    },
+    ReadDownload {
+        timeout_ms: u64,
+        label: String,
+    },
+    GetStorage {
+        key: String,
+        label: String,
+    },
+    SetStorage {
+        key: String,
+        value: String,
+    },
+    ClearCookies {
+        /// Only drop cookies visible to the current page instead of all of them.
+        #[serde(default)]
+        current_origin: bool,
+    },
+    GrantPermission {
+        name: String,
+    },
+    SetWindowBounds {
+        /// Required unless `maximize` is set.
+        #[serde(default)]
+        width: Option<u32>,
+        #[serde(default)]
+        height: Option<u32>,
+        /// Maximize the window instead of sizing it to `width` x `height`.
+        #[serde(default)]
+        maximize: bool,
+    },
+    /// Fails the whole task unless the condition holds: `selector` matches
+    /// an element and/or `text_contains` appears in it (or in the page).
+    Assert {
+        #[serde(default)]
+        selector: Option<String>,
+        #[serde(default)]
+        text_contains: Option<String>,
+    },
+    Done {
+        summary: String,
+    },
+    DismissModal,
+    ScrollIntoView {
+        selector: String,
+    },
     NewTab,
+    SwitchToOldestTab,
+    Activate,
+    GoToHistory {
+        title_contains: String,
+    },
+}
+
+impl Step {
+    /// Coarse category used to color-code steps in the web log.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Step::Navigate { .. }
+            | Step::FollowLink { .. }
+            | Step::NewTab
+            | Step::SwitchToOldestTab
+            | Step::Activate
+            | Step::GoToHistory { .. }
+            | Step::ScrollIntoView { .. } => "navigate",
+            Step::Click { .. }
+            | Step::ClickNth { .. }
+            | Step::RightClick { .. }
+            | Step::SubmitForm { .. }
+            | Step::DismissModal => "click",
+            Step::TypeInto { .. }
+            | Step::TypeByLabel { .. }
+            | Step::ClearInput { .. }
+            | Step::SetChecked { .. }
+            | Step::PressKey { .. }
+            | Step::SetStorage { .. }
+            | Step::ClearCookies { .. }
+            | Step::GrantPermission { .. }
+            | Step::SetWindowBounds { .. } => "input",
+            Step::Extract { .. }
+            | Step::ExtractLinks { .. }
+            | Step::ExtractTable { .. }
+            | Step::CountElements { .. }
+            | Step::GetAttribute { .. }
+            | Step::PerfMetrics { .. }
+            | Step::ClearExtractions
+            | Step::GetStorage { .. }
+            | Step::PageInfo { .. }
+            | Step::Screenshot
+            | Step::FullPageScreenshot
+            | Step::ScreenshotElement { .. }
+            | Step::ReadDownload { .. }
+            | Step::Assert { .. } => "extract",
+            Step::WaitFor { .. }
+            | Step::WaitForGone { .. }
+            | Step::WaitForUrl { .. }
+            | Step::WaitForStable { .. }
+            | Step::WaitForDownload { .. } => "wait",
+            Step::Done { .. } => "done",
+        }
+    }
+
+    /// Whether this step clicks or types into the page, and so may need the
+    /// tab in front to receive focus-dependent events.
+    pub fn is_interactive(&self) -> bool {
+        matches!(
+            self,
+            Step::Click { .. }
+                | Step::ClickNth { .. }
+                | Step::RightClick { .. }
+                | Step::SubmitForm { .. }
+                | Step::DismissModal
+                | Step::TypeInto { .. }
+                | Step::TypeByLabel { .. }
+                | Step::ClearInput { .. }
+                | Step::SetChecked { .. }
+                | Step::PressKey { .. }
+        )
+    }
+
+    /// Text this step sends to the page, which may be sensitive.
+    pub fn secret(&self) -> Option<&str> {
+        match self {
+            Step::TypeInto { text, .. } | Step::TypeByLabel { text, .. } => Some(text),
+            Step::SetStorage { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+
+    /// A copy with `secret()` replaced by `REDACTED`, safe to log or persist.
+    pub fn redacted(&self) -> Step {
+        let mut step = self.clone();
+        match &mut step {
+            Step::TypeInto { text, .. } | Step::TypeByLabel { text, .. } => {
+                *text = REDACTED.to_string()
+            }
+            Step::SetStorage { value, .. } => *value = REDACTED.to_string(),
+            _ => {}
+        }
+        step
+    }
+}
+
+/// Placeholder written in place of sensitive values.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Masks sensitive values in text that gets logged or persisted.
+///
+/// Starts with the comma-separated literals in REDACT_VALUES and learns
+/// anything typed into a password field.
+#[derive(Debug, Default)]
+pub struct Redactor {
+    values: Vec<String>,
+}
+
+impl Redactor {
+    pub fn from_env() -> Self {
+        let mut redactor = Redactor::default();
+        if let Ok(list) = std::env::var("REDACT_VALUES") {
+            for value in list.split(',') {
+                redactor.add(value.trim());
+            }
+        }
+        redactor
+    }
+
+    /// Start masking `value`. Empty and already-known values are ignored.
+    pub fn add(&mut self, value: &str) {
+        if !value.is_empty() && !self.values.iter().any(|v| v == value) {
+            self.values.push(value.to_string());
+        }
+    }
+
+    /// Whether the step's secret contains a value being masked.
+    pub fn matches(&self, step: &Step) -> bool {
+        step.secret()
+            .is_some_and(|s| self.values.iter().any(|v| s.contains(v.as_str())))
+    }
+
+    pub fn mask(&self, text: &str) -> String {
+        self.values.iter().fold(text.to_string(), |masked, v| {
+            masked.replace(v.as_str(), REDACTED)
+        })
+    }
+}
+
+/// A step plus the LLM's optional one-line rationale for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+    #[serde(flatten)]
+    pub step: Step,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Parse a Step out of a raw LLM reply.
+///
+/// Tolerates markdown code fences, prose before or after the JSON object,
+/// and surrounding whitespace. The first JSON object found is used.
+pub fn parse_step(raw: &str) -> Result<Step> {
+    parse_first_object(raw)
+}
+
+/// Like `parse_step`, but keeps the optional `reason` field.
+pub fn parse_decision(raw: &str) -> Result<Decision> {
+    parse_first_object(raw)
+}
+
+fn parse_first_object<T: serde::de::DeserializeOwned>(raw: &str) -> Result<T> {
+    let mut body = raw.trim();
+
+    // Prefer the contents of a fenced block if there is one
+    if let Some((_, after_fence)) = body.split_once("```") {
+        body = after_fence.split("```").next().unwrap_or(after_fence);
+    }
+
+    let start = body
+        .find('{')
+        .ok_or_else(|| anyhow!("No JSON object in LLM response"))?;
+
+    // Deserialize only the first value so trailing prose is ignored
+    match serde_json::Deserializer::from_str(&body[start..])
+        .into_iter::<T>()
+        .next()
+    {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(e)) => Err(anyhow!("Failed to parse LLM response: {}", e)),
+        None => Err(anyhow!("No JSON object in LLM response")),
+    }
 }
 
 /// What the agent observes after executing a step.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PageState {
     pub url: String,
+    /// HTTP status of the main document, when known.
+    pub http_status: Option<u16>,
     pub title: String,
     pub dom_snapshot: String,
     pub extracted: Vec<Extraction>,
     pub error: Option<String>,
+    /// Things the agent did on its own that the Brain should know about.
+    pub notes: Vec<String>,
+    /// Pages visited this task as (title, url), for GoToHistory.
+    pub recent_pages: Vec<(String, String)>,
+    /// Recent console errors/warnings on this page (CAPTURE_CONSOLE=1).
+    pub console_errors: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +352,13 @@ pub struct Extraction {
     pub content: String,
 }
 
+/// How a task ended.
+#[derive(Debug, Clone)]
+pub enum TaskOutcome {
+    Complete { summary: String },
+    Failed { message: String },
+}
+
 /// A message in the conversation history sent to the LLM.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
@@ -40,3 +368,389 @@ pub struct ChatMessage {
 
 pub const MAX_STEPS_PER_TASK: usize = 25;
 pub const DOM_SNAPSHOT_MAX_CHARS: usize = 4000;
+/// Default cap on extracted text, overridable with EXTRACT_MAX_CHARS.
+pub const EXTRACT_MAX_CHARS: usize = 2000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_action() {
+        let s = |v: &str| v.to_string();
+        let cases = [
+            (
+                r#"{"action":"Navigate","url":"https://example.com"}"#,
+                Step::Navigate {
+                    url: s("https://example.com"),
+                },
+            ),
+            (
+                r#"{"action":"FollowLink","href_contains":"/pricing"}"#,
+                Step::FollowLink {
+                    href_contains: s("/pricing"),
+                },
+            ),
+            (
+                r#"{"action":"GoToHistory","title_contains":"results"}"#,
+                Step::GoToHistory {
+                    title_contains: s("results"),
+                },
+            ),
+            (r#"{"action":"NewTab"}"#, Step::NewTab),
+            (r#"{"action":"SwitchToOldestTab"}"#, Step::SwitchToOldestTab),
+            (r#"{"action":"Activate"}"#, Step::Activate),
+            (
+                r#"{"action":"ScrollIntoView","selector":".faq"}"#,
+                Step::ScrollIntoView {
+                    selector: s(".faq"),
+                },
+            ),
+            (
+                r#"{"action":"Click","selector":"[data-eid=\"[e3]\"]"}"#,
+                Step::Click {
+                    selector: s("[data-eid=\"[e3]\"]"),
+                    fallbacks: Vec::new(),
+                },
+            ),
+            (
+                r#"{"action":"ClickNth","selector":"h3","index":2}"#,
+                Step::ClickNth {
+                    selector: s("h3"),
+                    index: 2,
+                },
+            ),
+            (
+                r#"{"action":"RightClick","selector":"li.file"}"#,
+                Step::RightClick {
+                    selector: s("li.file"),
+                },
+            ),
+            (
+                r#"{"action":"SubmitForm","selector":"input"}"#,
+                Step::SubmitForm {
+                    selector: s("input"),
+                },
+            ),
+            (r#"{"action":"DismissModal"}"#, Step::DismissModal),
+            (
+                r#"{"action":"TypeInto","selector":"input","text":"rust"}"#,
+                Step::TypeInto {
+                    selector: s("input"),
+                    text: s("rust"),
+                    fallbacks: Vec::new(),
+                },
+            ),
+            (
+                r#"{"action":"TypeByLabel","label":"Email","text":"a@b.c"}"#,
+                Step::TypeByLabel {
+                    label: s("Email"),
+                    text: s("a@b.c"),
+                },
+            ),
+            (
+                r#"{"action":"ClearInput","selector":"input"}"#,
+                Step::ClearInput {
+                    selector: s("input"),
+                },
+            ),
+            (
+                r#"{"action":"SetChecked","selector":"input","checked":false}"#,
+                Step::SetChecked {
+                    selector: s("input"),
+                    checked: false,
+                },
+            ),
+            (
+                r#"{"action":"PressKey","key":"Enter"}"#,
+                Step::PressKey { key: s("Enter") },
+            ),
+            (
+                r#"{"action":"SetStorage","key":"consent","value":"yes"}"#,
+                Step::SetStorage {
+                    key: s("consent"),
+                    value: s("yes"),
+                },
+            ),
+            (
+                r#"{"action":"GrantPermission","name":"geolocation"}"#,
+                Step::GrantPermission {
+                    name: s("geolocation"),
+                },
+            ),
+            (
+                r#"{"action":"SetWindowBounds","width":1280,"height":800}"#,
+                Step::SetWindowBounds {
+                    width: Some(1280),
+                    height: Some(800),
+                    maximize: false,
+                },
+            ),
+            (
+                r#"{"action":"Extract","selector":"body","label":"main"}"#,
+                Step::Extract {
+                    selector: s("body"),
+                    label: s("main"),
+                },
+            ),
+            (
+                r#"{"action":"ExtractLinks","label":"links"}"#,
+                Step::ExtractLinks { label: s("links") },
+            ),
+            (
+                r#"{"action":"ExtractTable","selector":"main","label":"prices"}"#,
+                Step::ExtractTable {
+                    selector: s("main"),
+                    label: s("prices"),
+                },
+            ),
+            (
+                r#"{"action":"CountElements","selector":"li","label":"items"}"#,
+                Step::CountElements {
+                    selector: s("li"),
+                    label: s("items"),
+                },
+            ),
+            (
+                r#"{"action":"GetAttribute","selector":"a.next","attribute":"href","label":"next"}"#,
+                Step::GetAttribute {
+                    selector: s("a.next"),
+                    attribute: s("href"),
+                    label: s("next"),
+                },
+            ),
+            (
+                r#"{"action":"PerfMetrics","label":"timing"}"#,
+                Step::PerfMetrics { label: s("timing") },
+            ),
+            (r#"{"action":"ClearExtractions"}"#, Step::ClearExtractions),
+            (
+                r#"{"action":"GetStorage","key":"token","label":"tok"}"#,
+                Step::GetStorage {
+                    key: s("token"),
+                    label: s("tok"),
+                },
+            ),
+            (
+                r#"{"action":"PageInfo","label":"scroll"}"#,
+                Step::PageInfo { label: s("scroll") },
+            ),
+            (r#"{"action":"Screenshot"}"#, Step::Screenshot),
+            (
+                r#"{"action":"FullPageScreenshot"}"#,
+                Step::FullPageScreenshot,
+            ),
+            (
+                r#"{"action":"ScreenshotElement","selector":"main"}"#,
+                Step::ScreenshotElement {
+                    selector: s("main"),
+                },
+            ),
+            (
+                r#"{"action":"ReadDownload","timeout_ms":15000,"label":"export"}"#,
+                Step::ReadDownload {
+                    timeout_ms: 15000,
+                    label: s("export"),
+                },
+            ),
+            (
+                r#"{"action":"WaitFor","selector":"input","timeout_ms":5000}"#,
+                Step::WaitFor {
+                    selector: s("input"),
+                    timeout_ms: 5000,
+                },
+            ),
+            (
+                r#"{"action":"WaitForGone","selector":".spinner","timeout_ms":8000}"#,
+                Step::WaitForGone {
+                    selector: s(".spinner"),
+                    timeout_ms: 8000,
+                },
+            ),
+            (
+                r#"{"action":"WaitForUrl","pattern":"*/dashboard*","timeout_ms":10000}"#,
+                Step::WaitForUrl {
+                    pattern: s("*/dashboard*"),
+                    timeout_ms: 10000,
+                },
+            ),
+            (
+                r#"{"action":"WaitForStable","selector":".drawer","timeout_ms":3000}"#,
+                Step::WaitForStable {
+                    selector: s(".drawer"),
+                    timeout_ms: 3000,
+                },
+            ),
+            (
+                r#"{"action":"WaitForDownload","timeout_ms":15000}"#,
+                Step::WaitForDownload { timeout_ms: 15000 },
+            ),
+            (
+                r#"{"action":"Done","summary":"all good"}"#,
+                Step::Done {
+                    summary: s("all good"),
+                },
+            ),
+        ];
+        for (json, expected) in cases {
+            assert_eq!(parse_step(json).unwrap(), expected, "{}", json);
+        }
+    }
+
+    #[test]
+    fn parses_click_fallbacks() {
+        let step = parse_step(
+            r##"{"action":"Click","selector":"#buy","fallbacks":["button.buy","[data-eid=\"[e7]\"]"]}"##,
+        )
+        .unwrap();
+        assert!(matches!(
+            step,
+            Step::Click { selector, fallbacks } if selector == "#buy" && fallbacks.len() == 2
+        ));
+    }
+
+    #[test]
+    fn clear_cookies_defaults_to_all_origins() {
+        let step = parse_step(r#"{"action":"ClearCookies","current_origin":true}"#).unwrap();
+        assert!(matches!(
+            step,
+            Step::ClearCookies {
+                current_origin: true
+            }
+        ));
+
+        let step = parse_step(r#"{"action":"ClearCookies"}"#).unwrap();
+        assert!(matches!(
+            step,
+            Step::ClearCookies {
+                current_origin: false
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_maximize_only_window_bounds() {
+        let step = parse_step(r#"{"action":"SetWindowBounds","maximize":true}"#).unwrap();
+        assert!(matches!(
+            step,
+            Step::SetWindowBounds {
+                width: None,
+                height: None,
+                maximize: true
+            }
+        ));
+    }
+
+    #[test]
+    fn parses_assert_with_missing_fields() {
+        let step = parse_step(r#"{"action":"Assert","text_contains":"Order placed"}"#).unwrap();
+        assert!(matches!(
+            step,
+            Step::Assert { selector: None, text_contains: Some(text) } if text == "Order placed"
+        ));
+    }
+
+    #[test]
+    fn redacted_masks_only_the_secret() {
+        let step = Step::TypeInto {
+            selector: "input".into(),
+            text: "hunter2".into(),
+            fallbacks: Vec::new(),
+        };
+        assert_eq!(step.secret(), Some("hunter2"));
+        assert!(matches!(
+            step.redacted(),
+            Step::TypeInto { selector, text, .. } if selector == "input" && text == REDACTED
+        ));
+        assert_eq!(Step::NewTab.secret(), None);
+    }
+
+    #[test]
+    fn redactor_masks_known_values() {
+        let mut redactor = Redactor::default();
+        redactor.add("hunter2");
+        redactor.add("");
+        assert_eq!(
+            redactor.mask(r#"{"text":"hunter2"} and hunter2"#),
+            r#"{"text":"[REDACTED]"} and [REDACTED]"#
+        );
+        let step = Step::SetStorage {
+            key: "k".into(),
+            value: "xhunter2x".into(),
+        };
+        assert!(redactor.matches(&step));
+        assert!(!redactor.matches(&Step::NewTab));
+    }
+
+    #[test]
+    fn parses_decision_reason() {
+        let decision = parse_decision(
+            r#"{"action":"ClickNth","selector":"a","index":1,"reason":"Second result is the docs"}"#,
+        )
+        .unwrap();
+        assert!(matches!(decision.step, Step::ClickNth { index: 1, .. }));
+        assert_eq!(
+            decision.reason.as_deref(),
+            Some("Second result is the docs")
+        );
+
+        let decision = parse_decision(r#"{"action":"NewTab"}"#).unwrap();
+        assert!(decision.reason.is_none());
+    }
+
+    #[test]
+    fn strips_json_fence() {
+        let raw = "```json\n{\"action\":\"NewTab\"}\n```";
+        assert!(matches!(parse_step(raw).unwrap(), Step::NewTab));
+    }
+
+    #[test]
+    fn strips_bare_fence() {
+        let raw = "```\n{\"action\":\"Screenshot\"}\n```";
+        assert!(matches!(parse_step(raw).unwrap(), Step::Screenshot));
+    }
+
+    #[test]
+    fn ignores_surrounding_prose() {
+        let raw = "Sure! Next I'll open a tab:\n{\"action\":\"NewTab\"}\nLet me know.";
+        assert!(matches!(parse_step(raw).unwrap(), Step::NewTab));
+    }
+
+    #[test]
+    fn ignores_prose_around_fence() {
+        let raw = "Here you go:\n```json\n{\"action\":\"PressKey\",\"key\":\"Tab\"}\n```\nDone.";
+        assert!(matches!(parse_step(raw).unwrap(), Step::PressKey { key } if key == "Tab"));
+    }
+
+    #[test]
+    fn ignores_whitespace() {
+        let raw = "\n\t  {\"action\":\"NewTab\"}  \n\n";
+        assert!(matches!(parse_step(raw).unwrap(), Step::NewTab));
+    }
+
+    #[test]
+    fn rejects_empty_input() {
+        assert!(parse_step("").is_err());
+        assert!(parse_step("   ").is_err());
+    }
+
+    #[test]
+    fn rejects_prose_without_json() {
+        assert!(parse_step("I think we should click the button.").is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_action() {
+        assert!(parse_step(r#"{"action":"Teleport","to":"moon"}"#).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_field() {
+        assert!(parse_step(r#"{"action":"Navigate"}"#).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_json() {
+        assert!(parse_step(r#"{"action":"Navigate","url":"https://exa"#).is_err());
+    }
+}